@@ -1,6 +1,8 @@
-use libactionkv::ActionKV;
 use std::collections::HashMap;
 
+use bincode::Options as _;
+use libactionkv::{ActionKV, DumpFormat, Options, Recovery};
+
 #[cfg(target_os = "windows")]
 const USAGE: &str = "
 Usage:
@@ -8,6 +10,17 @@ Usage:
     akv_disk.exe FILE delete KEY
     akv_disk.exe FILE insert KEY VALUE
     akv_disk.exe FILE update KEY VALUE
+    akv_disk.exe FILE compact
+    akv_disk.exe FILE list [PREFIX]
+    akv_disk.exe FILE backup DEST
+    akv_disk.exe FILE restore BACKUP
+    akv_disk.exe FILE export [--format jsonl|csv]
+    akv_disk.exe FILE import [--format jsonl|csv]
+    akv_disk.exe FILE tail [--follow] [OFFSET]
+
+Add --recover=skip to read a damaged FILE by skipping corrupt records,
+or --recover=truncate to also cut off a record left half-written by a crash.
+--recover=skip leaves FILE unchanged, so it cannot be used to write.
 ";
 
 #[cfg(not(target_os = "windows"))]
@@ -17,61 +30,215 @@ Usage:
     akv_disk FILE delete KEY
     akv_disk FILE insert KEY VALUE
     akv_disk FILE update KEY VALUE
+    akv_disk FILE compact
+    akv_disk FILE list [PREFIX]
+    akv_disk FILE backup DEST
+    akv_disk FILE restore BACKUP
+    akv_disk FILE export [--format jsonl|csv]
+    akv_disk FILE import [--format jsonl|csv]
+    akv_disk FILE tail [--follow] [OFFSET]
+
+Add --recover=skip to read a damaged FILE by skipping corrupt records,
+or --recover=truncate to also cut off a record left half-written by a crash.
+--recover=skip leaves FILE unchanged, so it cannot be used to write.
 ";
 
 type ByteStr = [u8];
 type ByteString = Vec<u8>;
 
-fn store_index_on_disk(a: &mut ActionKV, index_key: &ByteStr) {
-    a.index.remove(index_key);
-    let index_as_bytes = bincode::serialize(&a.index).unwrap();
-    a.index = std::collections::HashMap::new();
-    a.insert(index_key, &index_as_bytes).unwrap();
+// index 保存在数据文件旁边的 checkpoint 文件里，load 时只需扫描 checkpoint 之后的记录
+// checkpoint 就是库的 hint 文件（FILE.hint，格式见 hint.rs）：带 magic、版本号和 checksum，
+// 打开时和数据文件的长度、末尾的校验和核对，对不上就完整扫描一次
+// checkpoint 之后的记录超过这个数才重写 checkpoint，避免每次写入都重写整个 index
+const CHECKPOINT_EVERY: u64 = 1000;
+
+// 旧版本的 akv_disk 每次写入后把整个 index 用 bincode 写在这个 key 下
+const LEGACY_INDEX_KEY: &ByteStr = b"+index";
+
+// export 和 import 的格式，默认是 jsonl
+fn dump_format(flag: Option<&String>, name: Option<&String>) -> DumpFormat {
+    match (flag.map(String::as_str), name) {
+        (None, _) => DumpFormat::default(),
+        (Some("--format"), Some(name)) => name.parse().expect(USAGE),
+        _ => panic!("{}", USAGE),
+    }
 }
 
-fn main() {
-    const INDEX_KEY: &ByteStr = b"+index";
+// --recover 指定加载时的恢复方式，默认遇到任何损坏都失败
+fn recovery(flag: &str) -> Recovery {
+    match flag {
+        "--recover=skip" => Recovery::Skip,
+        "--recover=truncate" => Recovery::Truncate,
+        _ => panic!("{}", USAGE),
+    }
+}
+
+// 删除旧版本留下的 +index 记录，index 现在由 checkpoint 恢复，它已经没有用处
+// 只有旧格式写入（没有版本号）并且能解码成 index 的才是它，同名的普通 key 不受影响
+fn migrate_legacy_index(a: &mut ActionKV) {
+    if a.version(LEGACY_INDEX_KEY).expect("unable to read +index") != Some(0) {
+        return;
+    }
+    let value = a.get(LEGACY_INDEX_KEY).expect("unable to read +index").unwrap_or_default();
+    let options = bincode::DefaultOptions::new().with_fixint_encoding();
+    if options.deserialize::<HashMap<ByteString, u64>>(&value).is_ok() {
+        a.delete(LEGACY_INDEX_KEY).expect("unable to remove +index");
+        a.sync().expect("unable to sync file");
+        eprintln!("removed the index record left by an older akv_disk");
+    }
+}
 
-    let args: Vec<String> = std::env::args().collect();
-    let fname = args.get(1).expect(&USAGE);
-    let action = args.get(2).expect(&USAGE).as_ref();
-    let key = args.get(3).expect(&USAGE).as_ref();
+// 写入之后落盘，必要时重写 checkpoint
+fn finish(mut a: ActionKV, unindexed: u64) {
+    if unindexed >= CHECKPOINT_EVERY {
+        a.close().expect("unable to write checkpoint");
+    } else {
+        a.sync().expect("unable to sync file");
+    }
+}
+
+// 打印 OFFSET 之后已提交的变更，--follow 时一直等待新的写入
+// 结束时把下次继续用的位置打印到 stderr
+fn tail(path: &std::path::Path, args: &[String]) {
+    let follow = args.iter().any(|arg| arg == "--follow");
+    let from = args
+        .iter()
+        .find(|arg| *arg != "--follow")
+        .map_or(0, |offset| offset.parse().expect(USAGE));
+    let a = ActionKV::open(path).expect("unable to open file");
+    let mut changes = a.subscribe(from).expect("unable to read file");
+    loop {
+        for change in changes.by_ref() {
+            let change = change.expect("unable to read change");
+            // 默认命名空间以外的变更带上命名空间 id
+            let at = match change.namespace {
+                0 => change.position.to_string(),
+                id => format!("{} ns{}", change.position, id),
+            };
+            match change.value {
+                Some(value) => println!("{} set {:?} {:?}", at, change.key, value),
+                None => println!("{} delete {:?}", at, change.key),
+            }
+        }
+        if !follow {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    eprintln!("next offset {}", changes.position());
+}
+
+fn main() {
+    let mut args: Vec<String> = std::env::args().collect();
+    let recover = args.iter().position(|arg| arg.starts_with("--recover="));
+    let recovery = recover.map_or(Recovery::Strict, |i| recovery(&args.remove(i)));
+    let fname = args.get(1).expect(USAGE);
+    let action = args.get(2).expect(USAGE).as_ref();
+    let maybe_key = args.get(3);
     let maybe_value = args.get(4);
 
     let path = std::path::Path::new(&fname);
-    let mut a = ActionKV::open(path).expect("unable to open file");
 
-    a.load().expect("unable to load data");
+    // skip 不截断残缺的尾部，之后追加的记录下次加载时读不到，写入要用 truncate
+    let writes = ["insert", "update", "delete", "import"].contains(&action);
+    if writes && recovery == Recovery::Skip {
+        panic!("--recover=skip is read-only, use --recover=truncate\n{}", USAGE);
+    }
 
-    match action {
-        "get" => {
-            let index_as_bytes = a.get(&INDEX_KEY).unwrap().unwrap();
+    // 从备份恢复出 FILE，FILE 不能已经存在，所以不能像其他命令那样先打开它
+    if action == "restore" {
+        let backup = std::path::Path::new(maybe_key.expect(USAGE));
+        let a = ActionKV::restore(backup, path, Options::default()).expect("unable to restore");
+        println!("restored {} keys", a.keys().count());
+        return;
+    }
 
-            let index_decoded = bincode::deserialize(&index_as_bytes);
+    // 只读取日志，不加载 index：写者可能正在追加，严格加载会因为不完整的尾部而失败
+    if action == "tail" {
+        tail(path, &args[3..]);
+        return;
+    }
+
+    let mut a = ActionKV::open(path).expect("unable to open file");
 
-            let index: HashMap<ByteString, u64> = index_decoded.unwrap();
+    // 没有 checkpoint 时 records 是整个文件的记录数
+    let report = a.load_with(recovery).expect("unable to load data, see --recover");
+    let unindexed = report.records;
+    // 有残缺尾部时不能写入，留到下一次截断或压缩之后再删除
+    if a.torn_tail().is_none() {
+        migrate_legacy_index(&mut a);
+    }
+    for corruption in &report.corrupt {
+        eprintln!("skipped {}", corruption);
+    }
+    if let Some(tail) = report.torn_tail {
+        match report.truncated_to {
+            Some(_) => eprintln!("truncated torn tail at offset {}", tail),
+            None => eprintln!("ignored torn tail at offset {}", tail),
+        }
+    }
 
-            match index.get(key) {
+    match action {
+        "get" => {
+            let key: &ByteStr = maybe_key.expect(USAGE).as_ref();
+            match a.get(key).unwrap() {
                 None => eprintln!("{:?} not found", key),
-                Some(&i) => {
-                    let kv = a.get_at(i).unwrap();
-                    println!("{:?}", kv.value)
-                }
+                Some(value) => println!("{:?}", value),
             }
         }
 
-        "delete" => a.delete(key).unwrap(),
+        "delete" => {
+            let key = maybe_key.expect(USAGE).as_ref();
+            a.delete(key).unwrap();
+            finish(a, unindexed + 1);
+        }
 
         "insert" => {
-            let value = maybe_value.expect(&USAGE).as_ref();
+            let key = maybe_key.expect(USAGE).as_ref();
+            let value = maybe_value.expect(USAGE).as_ref();
             a.insert(key, value).unwrap();
-            store_index_on_disk(&mut a, INDEX_KEY);
+            finish(a, unindexed + 1);
         }
 
         "update" => {
-            let value = maybe_value.expect(&USAGE).as_ref();
+            let key = maybe_key.expect(USAGE).as_ref();
+            let value = maybe_value.expect(USAGE).as_ref();
             a.update(key, value).unwrap();
-            store_index_on_disk(&mut a, INDEX_KEY);
+            finish(a, unindexed + 1);
+        }
+
+        "compact" => {
+            // 压缩后偏移全部改变，compact 会重写 checkpoint
+            a.compact().unwrap();
+            finish(a, 0);
+        }
+
+        "list" => {
+            let prefix: &ByteStr = maybe_key.map_or(b"", |prefix| prefix.as_ref());
+            for kv in a.prefix(prefix) {
+                let (key, value) = kv.unwrap();
+                println!("{:?} {:?}", key, value)
+            }
+        }
+
+        "backup" => {
+            let dest = std::path::Path::new(maybe_key.expect(USAGE));
+            let report = a.backup(dest).expect("unable to back up");
+            println!("backed up {} records ({} bytes)", report.records, report.bytes);
+        }
+
+        // 写到标准输出
+        "export" => {
+            let format = dump_format(maybe_key, maybe_value);
+            a.export(std::io::stdout().lock(), format).expect("unable to export");
+        }
+
+        // 从标准输入读取
+        "import" => {
+            let format = dump_format(maybe_key, maybe_value);
+            let count = a.import(std::io::stdin().lock(), format).expect("unable to import");
+            eprintln!("imported {} keys", count);
+            finish(a, unindexed + count);
         }
         _ => eprintln!("{}", &USAGE),
     }
@@ -0,0 +1,96 @@
+use std::path::Path;
+use std::process;
+
+use libactionkv::{offset_of, segment_of, ActionKV, FsckReport, Options};
+
+#[cfg(target_os = "windows")]
+const USAGE: &str = "
+Usage:
+    akv_fsck.exe [--json] FILE
+
+Exits with status 1 when corrupt records or a torn tail are found.
+";
+
+#[cfg(not(target_os = "windows"))]
+const USAGE: &str = "
+Usage:
+    akv_fsck [--json] FILE
+
+Exits with status 1 when corrupt records or a torn tail are found.
+";
+
+// 位置显示为 段号:段内偏移
+fn position(position: u64) -> String {
+    format!("{}:{}", segment_of(position), offset_of(position))
+}
+
+fn print_text(fname: &str, report: &FsckReport) {
+    println!(
+        "{}: {} bytes, {} records, end at {}",
+        fname,
+        report.bytes,
+        report.records,
+        position(report.end)
+    );
+    for corruption in &report.corrupt {
+        if corruption.expected == corruption.actual {
+            println!(
+                "invalid record at {}: {} bytes, crc {:08x} matches",
+                position(corruption.offset),
+                corruption.len,
+                corruption.expected
+            );
+            continue;
+        }
+        println!(
+            "corrupt record at {}: {} bytes, crc expected {:08x}, actual {:08x}",
+            position(corruption.offset),
+            corruption.len,
+            corruption.expected,
+            corruption.actual
+        );
+    }
+    if let Some(tail) = report.torn_tail {
+        println!("torn tail at {}", position(tail));
+    }
+    println!("incomplete batches: {}", report.incomplete_batches);
+    println!("live keys: {} ({} bytes)", report.live_keys, report.live_bytes);
+    println!("namespaces: {}", report.namespaces);
+    println!("duplicates: {}", report.duplicates);
+    println!("tombstones: {}", report.tombstones);
+    println!("expired: {}", report.expired);
+    println!("dead records: {} ({} bytes)", report.dead_records, report.dead_bytes);
+    if report.is_clean() {
+        println!("clean");
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (json, fname) = match args.as_slice() {
+        [flag, fname] if flag == "--json" => (true, fname),
+        [fname] => (false, fname),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    // 打开会创建不存在的文件，检查工具不应该留下新文件
+    let path = Path::new(fname);
+    let meta = path.metadata().expect("unable to open file");
+    // 目录是分段存储，只读取不写入，段大小无关紧要
+    let segment_size = meta.is_dir().then_some(u64::MAX);
+    let options = Options { segment_size, ..Options::default() };
+    let a = ActionKV::open_with(path, options).expect("unable to open file");
+    let report = a.fsck().expect("unable to check file");
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    } else {
+        print_text(fname, &report);
+    }
+    if !report.is_clean() {
+        process::exit(1);
+    }
+}
@@ -11,6 +11,7 @@ Usage:
     akv_mem.exe FILE delete KEY
     akv_mem.exe FILE insert KEY VALUE
     akv_mem.exe FILE update KEY VALUE
+    akv_mem.exe FILE list [PREFIX]
 ";
 
 #[cfg(not(target_os = "windows"))]
@@ -20,13 +21,14 @@ Usage:
     akv_mem FILE delete KEY
     akv_mem FILE insert KEY VALUE
     akv_mem FILE update KEY VALUE
+    akv_mem FILE list [PREFIX]
 ";
 
 fn main() {
     let args: Vec<String> = std::env::args().collect();
-    let fname = args.get(1).expect(&USAGE);
-    let action = args.get(2).expect(&USAGE).as_ref();
-    let key = args.get(3).expect(&USAGE).as_ref();
+    let fname = args.get(1).expect(USAGE);
+    let action = args.get(2).expect(USAGE).as_ref();
+    let maybe_key = args.get(3);
     let maybe_value = args.get(4);
 
     // rust 封装了 path 操作，消除了系统间差异，关于path的最好都用系统库
@@ -36,23 +38,39 @@ fn main() {
     store.load().expect("unable to load data");
 
     match action {
-        "get" => match store.get(key).unwrap() {
-            None => eprintln!("{:?} not found", key),
-            Some(value) => println!("{:?}", String::from_utf8_lossy(&value)),
+        "get" => {
+            let key = maybe_key.expect(USAGE).as_ref();
+            match store.get(key).unwrap() {
+                None => eprintln!("{:?} not found", key),
+                Some(value) => println!("{:?}", String::from_utf8_lossy(&value)),
+            }
         },
 
-        "delete" => store.delete(key).unwrap(),
+        "delete" => {
+            let key = maybe_key.expect(USAGE).as_ref();
+            store.delete(key).unwrap()
+        },
 
         "insert" => {
-            let value = maybe_value.expect(&USAGE).as_ref();
+            let key = maybe_key.expect(USAGE).as_ref();
+            let value = maybe_value.expect(USAGE).as_ref();
             store.insert(key, value).unwrap()
         },
 
         "update" => {
-            let value = maybe_value.expect(&USAGE).as_ref();
+            let key = maybe_key.expect(USAGE).as_ref();
+            let value = maybe_value.expect(USAGE).as_ref();
             store.update(key, value).unwrap()
         },
 
+        "list" => {
+            let prefix = maybe_key.map_or("", |prefix| prefix.as_str());
+            for kv in store.prefix(prefix.as_bytes()) {
+                let (key, value) = kv.unwrap();
+                println!("{:?} {:?}", String::from_utf8_lossy(&key), String::from_utf8_lossy(&value));
+            }
+        },
+
         _ => eprintln!("{}", &USAGE)
     }
 
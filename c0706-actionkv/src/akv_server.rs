@@ -0,0 +1,67 @@
+use std::net::{SocketAddr, TcpListener};
+
+use libactionkv::{ActionKV, Follower, SharedActionKV};
+
+// 默认使用 redis 的端口，redis-cli 不带参数就能连接
+#[cfg(target_os = "windows")]
+const USAGE: &str = "
+Usage:
+    akv_server.exe FILE [ADDR] [--replicate ADDR | --follow LEADER]
+
+ADDR defaults to 127.0.0.1:6379
+--replicate ADDR also ships the log to followers connecting to ADDR.
+--follow LEADER replicates from the leader's --replicate address and refuses
+writes until a client sends REPLICAOF NO ONE, which promotes it to a primary.
+";
+
+#[cfg(not(target_os = "windows"))]
+const USAGE: &str = "
+Usage:
+    akv_server FILE [ADDR] [--replicate ADDR | --follow LEADER]
+
+ADDR defaults to 127.0.0.1:6379
+--replicate ADDR also ships the log to followers connecting to ADDR.
+--follow LEADER replicates from the leader's --replicate address and refuses
+writes until a client sends REPLICAOF NO ONE, which promotes it to a primary.
+";
+
+// 取出 flag 和它后面的值
+fn take_flag(args: &mut Vec<String>, flag: &str) -> Option<String> {
+    let i = args.iter().position(|arg| arg == flag)?;
+    args.remove(i);
+    assert!(i < args.len(), "{}", USAGE);
+    Some(args.remove(i))
+}
+
+fn main() {
+    let mut args: Vec<String> = std::env::args().collect();
+    let replicate = take_flag(&mut args, "--replicate");
+    let follow = take_flag(&mut args, "--follow");
+    let fname = args.get(1).expect(USAGE);
+    let addr = args.get(2).map_or("127.0.0.1:6379", |addr| addr.as_str());
+
+    let path = std::path::Path::new(&fname);
+    let listener = TcpListener::bind(addr).expect("unable to listen");
+    println!("listening on {}", listener.local_addr().unwrap());
+
+    match (follow, replicate) {
+        (Some(_), Some(_)) => panic!("{}", USAGE),
+        // 从库不向其他从库发送日志，提升之后需要时用 --replicate 重新启动
+        (Some(leader), None) => {
+            let leader: SocketAddr = leader.parse().expect(USAGE);
+            let mut store = ActionKV::open(path).expect("unable to open file");
+            store.load().expect("unable to load data");
+            let follower = Follower::start(store, leader).expect("unable to start replication");
+            libactionkv::serve_follower(listener, follower).expect("unable to accept connections");
+        }
+        (None, replicate) => {
+            let store = SharedActionKV::open(path).expect("unable to open file");
+            if let Some(replicate) = replicate {
+                let followers = TcpListener::bind(replicate).expect("unable to listen");
+                let store = store.clone();
+                std::thread::spawn(move || libactionkv::serve_replication(followers, store));
+            }
+            libactionkv::serve(listener, store).expect("unable to accept connections");
+        }
+    }
+}
@@ -0,0 +1,213 @@
+// 供 tokio 程序使用的异步句柄，需要打开 async feature
+//
+// ActionKV 的读写都是阻塞的文件 I/O，直接在异步任务里调用会卡住运行时的工作线程
+// AsyncActionKV 把 ActionKV 交给一个专用的 I/O 线程，每个操作作为一个任务发过去，
+// 结果通过 oneshot 送回，等待结果的 future 不占用工作线程
+// 任务按发送的顺序逐个执行，语义和同步 API 相同：一个操作完成后，之后发出的操作都能看到它
+// 句柄可以 clone，所有句柄都被丢弃后 I/O 线程退出；需要写入 hint 时调用 close
+
+use std::io;
+use std::ops::{Bound, RangeBounds};
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use tokio::sync::oneshot;
+
+use crate::{ActionKV, ByteStr, ByteString, Options, WriteBatch};
+
+type Task = Box<dyn FnOnce(&mut ActionKV) + Send>;
+
+enum Job {
+    Run(Task),
+    // 关闭存储并让 I/O 线程退出
+    Close(oneshot::Sender<io::Result<()>>),
+}
+
+#[derive(Debug, Clone)]
+pub struct AsyncActionKV {
+    jobs: mpsc::Sender<Job>,
+}
+
+impl AsyncActionKV {
+    // 打开并加载存储，加载也在 I/O 线程上进行
+    pub async fn open(path: &Path) -> io::Result<Self> {
+        AsyncActionKV::open_with(path, Options::default()).await
+    }
+
+    pub async fn open_with(path: &Path, options: Options) -> io::Result<Self> {
+        let path = path.to_path_buf();
+        let (opened, receiver) = oneshot::channel();
+        let (jobs, queue) = mpsc::channel();
+        thread::Builder::new().name("actionkv-io".to_string()).spawn(move || {
+            let store = ActionKV::open_with(&path, options).and_then(|mut store| {
+                store.load()?;
+                Ok(store)
+            });
+            match store {
+                Ok(store) => {
+                    let _ = opened.send(Ok(()));
+                    run(store, queue);
+                }
+                Err(err) => {
+                    let _ = opened.send(Err(err));
+                }
+            }
+        })?;
+        receiver.await.map_err(|_| stopped())??;
+        Ok(AsyncActionKV { jobs })
+    }
+
+    pub async fn get(&self, key: &ByteStr) -> io::Result<Option<ByteString>> {
+        let key = key.to_vec();
+        self.call(move |store| store.get(&key)).await?
+    }
+
+    pub async fn contains_key(&self, key: &ByteStr) -> io::Result<bool> {
+        let key = key.to_vec();
+        self.call(move |store| store.contains_key(&key)).await
+    }
+
+    pub async fn get_with_version(&self, key: &ByteStr) -> io::Result<Option<(ByteString, u64)>> {
+        let key = key.to_vec();
+        self.call(move |store| store.get_with_version(&key)).await?
+    }
+
+    // 和 SharedActionKV 一样把结果收集成 Vec；range 的两端是自有的 key，可以送到 I/O 线程
+    pub async fn scan<R>(&self, range: R) -> io::Result<Vec<(ByteString, ByteString)>>
+    where
+        R: RangeBounds<ByteString> + Send + 'static,
+    {
+        self.call(move |store| {
+            let range: (Bound<&ByteStr>, Bound<&ByteStr>) = (
+                range.start_bound().map(Vec::as_slice),
+                range.end_bound().map(Vec::as_slice),
+            );
+            store.scan(range).collect()
+        })
+        .await?
+    }
+
+    pub async fn prefix(&self, prefix: &ByteStr) -> io::Result<Vec<(ByteString, ByteString)>> {
+        let prefix = prefix.to_vec();
+        self.call(move |store| store.prefix(&prefix).collect()).await?
+    }
+
+    pub async fn keys(&self) -> io::Result<Vec<ByteString>> {
+        self.call(|store| store.keys().cloned().collect()).await
+    }
+
+    pub async fn insert(&self, key: &ByteStr, value: &ByteStr) -> io::Result<()> {
+        let (key, value) = (key.to_vec(), value.to_vec());
+        self.call(move |store| store.insert(&key, &value)).await?
+    }
+
+    pub async fn insert_with_ttl(
+        &self,
+        key: &ByteStr,
+        value: &ByteStr,
+        ttl: Duration,
+    ) -> io::Result<()> {
+        let (key, value) = (key.to_vec(), value.to_vec());
+        self.call(move |store| store.insert_with_ttl(&key, &value, ttl)).await?
+    }
+
+    pub async fn insert_if_absent(&self, key: &ByteStr, value: &ByteStr) -> io::Result<u64> {
+        let (key, value) = (key.to_vec(), value.to_vec());
+        self.call(move |store| store.insert_if_absent(&key, &value)).await?
+    }
+
+    pub async fn update_if_version(
+        &self,
+        key: &ByteStr,
+        expected: u64,
+        value: &ByteStr,
+    ) -> io::Result<u64> {
+        let (key, value) = (key.to_vec(), value.to_vec());
+        self.call(move |store| store.update_if_version(&key, expected, &value)).await?
+    }
+
+    pub async fn update(&self, key: &ByteStr, value: &ByteStr) -> io::Result<()> {
+        let (key, value) = (key.to_vec(), value.to_vec());
+        self.call(move |store| store.update(&key, &value)).await?
+    }
+
+    pub async fn delete(&self, key: &ByteStr) -> io::Result<()> {
+        let key = key.to_vec();
+        self.call(move |store| store.delete(&key)).await?
+    }
+
+    pub async fn delete_if_version(&self, key: &ByteStr, expected: u64) -> io::Result<()> {
+        let key = key.to_vec();
+        self.call(move |store| store.delete_if_version(&key, expected)).await?
+    }
+
+    pub async fn write_batch(&self, batch: WriteBatch) -> io::Result<()> {
+        self.call(move |store| store.write_batch(&batch)).await?
+    }
+
+    pub async fn flush(&self) -> io::Result<()> {
+        self.call(|store| store.flush()).await?
+    }
+
+    pub async fn sync(&self) -> io::Result<()> {
+        self.call(|store| store.sync()).await?
+    }
+
+    pub async fn compact(&self) -> io::Result<()> {
+        self.call(|store| store.compact()).await?
+    }
+
+    // 在 I/O 线程上访问 ActionKV，用于上面没有包装的操作
+    // f 会阻塞之后的所有操作，不要在里面等待别的任务
+    pub async fn call<T, F>(&self, f: F) -> io::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut ActionKV) -> T + Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+        let task: Task = Box::new(move |store| {
+            let _ = sender.send(f(store));
+        });
+        self.jobs.send(Job::Run(task)).map_err(|_| stopped())?;
+        receiver.await.map_err(|_| stopped())
+    }
+
+    // 写入 hint 并关闭存储，之后所有句柄上的操作都返回 BrokenPipe
+    // 在 close 之前发出的操作仍然会执行
+    pub async fn close(self) -> io::Result<()> {
+        let (sender, receiver) = oneshot::channel();
+        self.jobs.send(Job::Close(sender)).map_err(|_| stopped())?;
+        receiver.await.map_err(|_| stopped())?
+    }
+}
+
+impl From<ActionKV> for AsyncActionKV {
+    // 已经打开并加载的存储，交给新的 I/O 线程
+    fn from(store: ActionKV) -> Self {
+        let (jobs, queue) = mpsc::channel();
+        thread::Builder::new()
+            .name("actionkv-io".to_string())
+            .spawn(move || run(store, queue))
+            .expect("unable to spawn actionkv I/O thread");
+        AsyncActionKV { jobs }
+    }
+}
+
+// I/O 线程的主循环
+fn run(mut store: ActionKV, queue: mpsc::Receiver<Job>) {
+    for job in queue {
+        match job {
+            Job::Run(task) => task(&mut store),
+            Job::Close(done) => {
+                let _ = done.send(store.close());
+                return;
+            }
+        }
+    }
+}
+
+fn stopped() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "actionkv I/O thread has stopped")
+}
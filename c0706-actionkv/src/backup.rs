@@ -0,0 +1,131 @@
+// 在线备份与恢复
+//
+// 备份基于快照（见 snapshot.rs），不阻塞写者：把快照中没有过期的记录原样写成一个单文件存储，
+// 相当于一份压缩过的镜像。记录不解压也不解密，备份不需要密钥，恢复后仍用原来的密钥读取
+// 读出的每条记录都经过 CRC 校验；先写临时文件，落盘后再改名，中途失败不会留下半份备份
+// 备份不含墓碑，末尾追加一个带版本号的 abort 标记，恢复后不会重新分配用过的版本号
+//
+// 恢复先用 Recovery::Strict 扫描整个备份，有任何损坏都不恢复
+// 记录写到目标旁边的临时位置，落盘后改名，再加载重建 index 并写入 hint
+// 目标已经存在时返回 AlreadyExists，不会覆盖已有的存储
+
+use std::fs::{self, File};
+use std::io;
+use std::io::BufWriter;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::batch;
+use crate::log::Log;
+use crate::record::Record;
+use crate::storage::suffixed;
+use crate::{ActionKV, Durability, FileStorage, LoadReport, Options, Recovery, Snapshot};
+
+// 恢复时每次追加的记录数
+const RESTORE_CHUNK: usize = 256;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BackupReport {
+    // 备份对应的源日志末尾，这个位置之前提交的写入都在备份里
+    pub end: u64,
+    pub records: u64,
+    pub bytes: u64,
+}
+
+pub(crate) fn backup(snapshot: &Snapshot, dest: &Path) -> io::Result<BackupReport> {
+    ensure_missing(dest)?;
+    let tmp = suffixed(dest, "tmp");
+    let mut report = BackupReport { end: snapshot.end(), ..BackupReport::default() };
+    if let Err(err) = write_records(snapshot, &tmp, &mut report) {
+        let _ = fs::remove_file(&tmp);
+        return Err(err);
+    }
+    fs::rename(&tmp, dest)?;
+    Ok(report)
+}
+
+fn write_records(snapshot: &Snapshot, path: &Path, report: &mut BackupReport) -> io::Result<()> {
+    let mut f = BufWriter::new(File::create(path)?);
+    for record in snapshot.records() {
+        report.bytes += record?.write(&mut f)?;
+        report.records += 1;
+    }
+    if snapshot.sequence() > 0 {
+        report.bytes += batch::abort_marker()?.with_version(snapshot.sequence()).write(&mut f)?;
+    }
+    // 改名之前必须落盘
+    let f = f.into_inner().map_err(|err| err.into_error())?;
+    f.sync_all()
+}
+
+pub(crate) fn restore(backup: &Path, path: &Path, options: Options) -> io::Result<ActionKV> {
+    ensure_missing(path)?;
+    // Log::open 会创建不存在的文件，先确认备份存在
+    fs::metadata(backup)?;
+    let source = Log::open(Arc::new(FileStorage::open(backup, false)?), &Options::default())?;
+
+    // 上一次恢复中断时留下的临时文件
+    let tmp = suffixed(path, "restore");
+    remove(&tmp)?;
+
+    if let Err(err) = copy_records(&source, &tmp, &options) {
+        let _ = remove(&tmp);
+        return Err(err);
+    }
+    fs::rename(&tmp, path)?;
+
+    let mut store = ActionKV::open_with(path, options)?;
+    store.load()?;
+    store.write_hint()?;
+    Ok(store)
+}
+
+// 把 source 中已提交的记录原样写入 path 处新建的存储
+fn copy_records(source: &Log, path: &Path, options: &Options) -> io::Result<()> {
+    // 只在最后落盘一次
+    let staging = Options { durability: Durability::Buffered, ..options.clone() };
+    let mut store = ActionKV::open_with(path, staging)?;
+    let mut report = LoadReport::default();
+    let mut chunk: Vec<Record> = Vec::with_capacity(RESTORE_CHUNK);
+    let mut copied = Ok(());
+    source.scan(0, Recovery::Strict, &mut report, |_, record| {
+        if copied.is_err() {
+            return;
+        }
+        chunk.push(record);
+        if chunk.len() == RESTORE_CHUNK {
+            copied = store.append(&chunk).map(drop);
+            chunk.clear();
+        }
+    })?;
+    copied?;
+    if !chunk.is_empty() {
+        store.append(&chunk)?;
+    }
+    store.sequence = report.max_version;
+    store.stamp_sequence()?;
+    store.sync()
+}
+
+// 删除单文件或分段存储的目录，不存在时什么也不做
+pub(crate) fn remove(path: &Path) -> io::Result<()> {
+    let removed = match fs::metadata(path) {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(err) => Err(err),
+    };
+    match removed {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+fn ensure_missing(path: &Path) -> io::Result<()> {
+    if path.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", path.display()),
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,174 @@
+// 批量写入：一组 put/delete 要么全部生效，要么全部不生效
+//
+// 磁盘上是 begin 标记、成员记录、commit 标记，三者用一次 write 追加
+// 标记是带 FLAG_BATCH 的记录，key 为空，value 的第一个字节是标记类型
+//   begin:  kind | count u32
+//   commit: kind | count u32 | checksum u32，checksum 是所有成员记录 checksum 的 CRC
+//   abort:  kind
+// 崩溃后日志末尾可能留下没有 commit 的批次，load 会丢弃它，
+// 并在下一次写入之前追加 abort，避免之后的记录被当成这个批次的成员
+
+use std::io;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::record::{Record, FLAG_BATCH};
+use crate::{ByteStr, ByteString, CRC};
+
+const BEGIN: u8 = 1;
+const COMMIT: u8 = 2;
+const ABORT: u8 = 3;
+
+#[derive(Debug, Default, Clone)]
+pub struct WriteBatch {
+    // value 为 None 表示删除
+    ops: Vec<(ByteString, Option<ByteString>)>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        WriteBatch::default()
+    }
+
+    pub fn insert(&mut self, key: &ByteStr, value: &ByteStr) -> &mut Self {
+        self.ops.push((key.to_vec(), Some(value.to_vec())));
+        self
+    }
+
+    pub fn delete(&mut self, key: &ByteStr) -> &mut Self {
+        self.ops.push((key.to_vec(), None));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    // 编码为 begin、成员、commit 三部分，成员由 encode 按顺序编码（版本号、压缩、加密）
+    // value 为 None 时编码为墓碑
+    pub(crate) fn to_records<F>(&self, mut encode: F) -> io::Result<Vec<Record>>
+    where
+        F: FnMut(&ByteStr, Option<&ByteStr>) -> io::Result<Record>,
+    {
+        let mut members = Vec::with_capacity(self.ops.len());
+        for (key, value) in &self.ops {
+            members.push(encode(key, value.as_deref())?);
+        }
+        wrap(members)
+    }
+}
+
+// 给编码好的成员加上 begin 和 commit 标记
+pub(crate) fn wrap(members: Vec<Record>) -> io::Result<Vec<Record>> {
+    let count = members.len() as u32;
+    let checksum = members_checksum(&members);
+    let mut records = Vec::with_capacity(members.len() + 2);
+    records.push(marker(BEGIN, &count.to_le_bytes())?);
+    records.extend(members);
+    let mut payload = [0; 8];
+    LittleEndian::write_u32(&mut payload[..4], count);
+    LittleEndian::write_u32(&mut payload[4..], checksum);
+    records.push(marker(COMMIT, &payload)?);
+    Ok(records)
+}
+
+pub(crate) fn abort_marker() -> io::Result<Record> {
+    marker(ABORT, b"")
+}
+
+fn marker(kind: u8, payload: &ByteStr) -> io::Result<Record> {
+    let mut value = vec![kind];
+    value.extend_from_slice(payload);
+    Record::new(FLAG_BATCH, b"", &value)
+}
+
+fn members_checksum<'a>(members: impl IntoIterator<Item = &'a Record>) -> u32 {
+    let mut digest = CRC.digest();
+    for record in members {
+        digest.update(&record.checksum.to_le_bytes());
+    }
+    digest.finalize()
+}
+
+// 扫描日志时正在收集成员的批次
+#[derive(Debug, Default)]
+pub(crate) struct Pending {
+    open: Option<(u32, Vec<(u64, Record)>)>,
+    // 被丢弃的未提交批次数
+    pub discarded: u64,
+}
+
+impl Pending {
+    pub fn is_open(&self) -> bool {
+        self.open.is_some()
+    }
+
+    // 处理一条记录，返回此时可以生效的记录
+    pub fn push(&mut self, position: u64, record: Record) -> io::Result<Vec<(u64, Record)>> {
+        if !record.is_batch_marker() {
+            match &mut self.open {
+                Some((count, members)) if members.len() < *count as usize => {
+                    members.push((position, record));
+                    return Ok(Vec::new());
+                }
+                // 成员已满却没有 commit，说明批次没有写完
+                Some(_) => self.discard(),
+                None => {}
+            }
+            return Ok(vec![(position, record)]);
+        }
+
+        let value = &record.value;
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid batch marker at offset {}", position),
+            )
+        };
+        match value.first() {
+            Some(&BEGIN) if value.len() == 5 => {
+                self.discard();
+                let count = LittleEndian::read_u32(&value[1..5]);
+                self.open = Some((count, Vec::new()));
+                Ok(Vec::new())
+            }
+            Some(&COMMIT) if value.len() == 9 => {
+                let count = LittleEndian::read_u32(&value[1..5]);
+                let checksum = LittleEndian::read_u32(&value[5..9]);
+                match self.open.take() {
+                    Some((expected, members))
+                        if expected == count
+                            && members.len() == count as usize
+                            && members_checksum(members.iter().map(|(_, r)| r)) == checksum =>
+                    {
+                        Ok(members)
+                    }
+                    Some(_) | None => {
+                        self.discarded += 1;
+                        Ok(Vec::new())
+                    }
+                }
+            }
+            Some(&ABORT) if value.len() == 1 => {
+                self.discard();
+                Ok(Vec::new())
+            }
+            _ => Err(invalid()),
+        }
+    }
+
+    // 扫描结束时仍未提交的批次也被丢弃
+    pub fn finish(&mut self) {
+        self.discard();
+    }
+
+    fn discard(&mut self) {
+        if self.open.take().is_some() {
+            self.discarded += 1;
+        }
+    }
+}
@@ -0,0 +1,21 @@
+// 判断 key 是否过期用的时钟，测试时可以换成手动拨动的时钟
+
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub trait Clock: fmt::Debug + Send + Sync {
+    // 当前时间，自 UNIX 纪元起的毫秒数
+    fn now(&self) -> u64;
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        // 系统时间早于 1970 年时当作 0
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64)
+    }
+}
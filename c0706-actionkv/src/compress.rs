@@ -0,0 +1,46 @@
+// value 的压缩
+// 使用的编码记录在记录标志的 CODEC_MASK 位上，见 record.rs
+// 只压缩 value，不包括附加字段；checksum 覆盖压缩后的字节，校验不需要先解压
+
+use std::io;
+
+use crate::record::{CODEC_MASK, FLAG_LZ4, FLAG_ZSTD};
+use crate::{ByteStr, ByteString};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    // 速度快，压缩率一般
+    Lz4,
+    // 压缩率高，level 越大越慢，zstd 的默认 level 是 3
+    Zstd { level: i32 },
+}
+
+// 返回编码对应的标志位和压缩后的数据
+// 压缩后没有变小时原样保存，标志位为 0
+pub(crate) fn compress(compression: Compression, value: &ByteStr) -> io::Result<(u8, ByteString)> {
+    let (flag, compressed) = match compression {
+        Compression::None => return Ok((0, value.to_vec())),
+        Compression::Lz4 => (FLAG_LZ4, lz4_flex::compress_prepend_size(value)),
+        Compression::Zstd { level } => (FLAG_ZSTD, zstd::bulk::compress(value, level)?),
+    };
+    if compressed.len() >= value.len() {
+        return Ok((0, value.to_vec()));
+    }
+    Ok((flag, compressed))
+}
+
+// 按 flags 中的编码解压
+pub(crate) fn decompress(flags: u8, stored: ByteString) -> io::Result<ByteString> {
+    match flags & CODEC_MASK {
+        0 => Ok(stored),
+        FLAG_LZ4 => lz4_flex::decompress_size_prepended(&stored)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+        FLAG_ZSTD => zstd::stream::decode_all(stored.as_slice()),
+        codec => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unknown compression codec {:02x}", codec),
+        )),
+    }
+}
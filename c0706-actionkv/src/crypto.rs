@@ -0,0 +1,87 @@
+// 静态加密：value 用 ChaCha20-Poly1305 加密，每条记录随机生成 nonce
+//
+// 加密后的 value：nonce [u8; 12] | 密文 | tag [u8; 16]
+// key 和附加字段不加密（index 和 hint 需要明文的 key），但和 flags 一起作为关联数据参与认证，
+// 密文不能被挪到别的 key 下，过期时间也不能被改掉
+// nonce 是随机的，同一个密钥加密的记录数应远小于 2^32，否则应更换密钥并压缩
+
+use std::fmt;
+use std::io;
+
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+
+use crate::{ByteStr, ByteString};
+
+const NONCE_LEN: usize = 12;
+
+#[derive(Clone)]
+pub struct EncryptionKey(Key);
+
+impl EncryptionKey {
+    pub fn new(bytes: [u8; 32]) -> Self {
+        EncryptionKey(Key::from(bytes))
+    }
+
+    // 长度不是 32 字节时返回 InvalidInput
+    pub fn from_slice(bytes: &ByteStr) -> io::Result<Self> {
+        let bytes: [u8; 32] = bytes.try_into().map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "encryption key must be 32 bytes")
+        })?;
+        Ok(EncryptionKey::new(bytes))
+    }
+
+    // 用操作系统的随机数生成一个新密钥
+    pub fn generate() -> Self {
+        EncryptionKey(ChaCha20Poly1305::generate_key(&mut OsRng))
+    }
+
+    pub fn as_bytes(&self) -> &ByteStr {
+        self.0.as_slice()
+    }
+}
+
+// 不在日志和调试输出中泄露密钥
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+pub(crate) fn encrypt(
+    key: &EncryptionKey,
+    aad: &ByteStr,
+    plaintext: &ByteStr,
+) -> io::Result<ByteString> {
+    let cipher = ChaCha20Poly1305::new(&key.0);
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, Payload { msg: plaintext, aad })
+        .map_err(|_| io::Error::other("encryption failed"))?;
+    let mut stored = nonce.to_vec();
+    stored.extend_from_slice(&ciphertext);
+    Ok(stored)
+}
+
+// 密钥不对或数据被篡改时返回 InvalidData
+pub(crate) fn decrypt(
+    key: &EncryptionKey,
+    aad: &ByteStr,
+    stored: &ByteStr,
+) -> io::Result<ByteString> {
+    if stored.len() < NONCE_LEN {
+        return Err(authentication_failed());
+    }
+    let (nonce, ciphertext) = stored.split_at(NONCE_LEN);
+    let cipher = ChaCha20Poly1305::new(&key.0);
+    cipher
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad })
+        .map_err(|_| authentication_failed())
+}
+
+fn authentication_failed() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "unable to decrypt value: wrong encryption key or tampered data",
+    )
+}
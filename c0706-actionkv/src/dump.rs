@@ -0,0 +1,177 @@
+// 导出、导入 JSON Lines 和 CSV
+//
+// 每个 kv 一行：key | key_encoding | value | value_encoding | expires
+// key 和 value 是合法的 UTF-8 时原样写出，encoding 为 utf8，否则用 base64 编码，encoding 为 base64
+// expires 是过期时间（自 UNIX 纪元起的毫秒数），没有过期时间时 JSON 中为 null，CSV 中为空
+// 导入时 JSON Lines 可以省略 encoding 和 expires，按 utf8、没有过期时间处理
+
+use std::fmt;
+use std::io;
+use std::io::prelude::*;
+use std::str::FromStr;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{ByteStr, ByteString, Expires, Scan};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DumpFormat {
+    // 每行一个 JSON 对象
+    #[default]
+    JsonLines,
+    // 第一行是表头
+    Csv,
+}
+
+impl FromStr for DumpFormat {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jsonl" => Ok(DumpFormat::JsonLines),
+            "csv" => Ok(DumpFormat::Csv),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown format {:?}, expected jsonl or csv", s),
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Encoding {
+    #[default]
+    Utf8,
+    Base64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Row {
+    key: String,
+    #[serde(default)]
+    key_encoding: Encoding,
+    value: String,
+    #[serde(default)]
+    value_encoding: Encoding,
+    #[serde(default)]
+    expires: Option<u64>,
+}
+
+fn encode(bytes: ByteString) -> (String, Encoding) {
+    match String::from_utf8(bytes) {
+        Ok(text) => (text, Encoding::Utf8),
+        Err(err) => (BASE64.encode(err.as_bytes()), Encoding::Base64),
+    }
+}
+
+fn decode(text: String, encoding: Encoding) -> io::Result<ByteString> {
+    match encoding {
+        Encoding::Utf8 => Ok(text.into_bytes()),
+        Encoding::Base64 => {
+            BASE64.decode(text).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+        }
+    }
+}
+
+impl Row {
+    fn new(key: ByteString, value: ByteString, expires: Option<u64>) -> Row {
+        let (key, key_encoding) = encode(key);
+        let (value, value_encoding) = encode(value);
+        Row { key, key_encoding, value, value_encoding, expires }
+    }
+
+    fn into_kv(self) -> io::Result<(ByteString, ByteString, Option<u64>)> {
+        let key = decode(self.key, self.key_encoding)?;
+        let value = decode(self.value, self.value_encoding)?;
+        Ok((key, value, self.expires))
+    }
+}
+
+// 按 key 的顺序写出 scan 遍历到的 kv，返回写出的行数
+pub(crate) fn export<W: Write>(
+    scan: Scan<'_>,
+    expires: &Expires,
+    out: W,
+    format: DumpFormat,
+) -> io::Result<u64> {
+    let rows = scan.map(|kv| {
+        kv.map(|(key, value)| {
+            // 不用 expiry：遍历途中过期的 key 仍然返回，它的过期时间也要写出
+            let expires = expires.get(&key).copied();
+            Row::new(key, value, expires)
+        })
+    });
+    let mut count = 0;
+    match format {
+        DumpFormat::JsonLines => {
+            let mut out = io::BufWriter::new(out);
+            for row in rows {
+                serde_json::to_writer(&mut out, &row?)?;
+                out.write_all(b"\n")?;
+                count += 1;
+            }
+            out.flush()?;
+        }
+        DumpFormat::Csv => {
+            let mut out = csv::Writer::from_writer(out);
+            for row in rows {
+                out.serialize(row?)?;
+                count += 1;
+            }
+            out.flush()?;
+        }
+    }
+    Ok(count)
+}
+
+// 逐行交给 put 写入，返回导入的行数
+// 遇到无法解析的行时返回 InvalidData，之前的行已经写入
+pub(crate) fn import<R, F>(input: R, format: DumpFormat, mut put: F) -> io::Result<u64>
+where
+    R: Read,
+    F: FnMut(&ByteStr, &ByteStr, Option<u64>) -> io::Result<()>,
+{
+    let mut count = 0;
+    match format {
+        DumpFormat::JsonLines => {
+            for (i, line) in io::BufReader::new(input).lines().enumerate() {
+                let (line, number) = (line?, i as u64 + 1);
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let row: Row = serde_json::from_str(&line).map_err(|err| at_line(number, err))?;
+                apply(&mut put, row, number)?;
+                count += 1;
+            }
+        }
+        DumpFormat::Csv => {
+            let mut input = csv::Reader::from_reader(input);
+            let headers = input.headers()?.clone();
+            for record in input.records() {
+                let record = record?;
+                let number = record.position().map_or(0, |position| position.line());
+                let row = record
+                    .deserialize(Some(&headers))
+                    .map_err(|err| at_line(number, err))?;
+                apply(&mut put, row, number)?;
+                count += 1;
+            }
+        }
+    }
+    Ok(count)
+}
+
+fn apply<F>(put: &mut F, row: Row, line: u64) -> io::Result<()>
+where
+    F: FnMut(&ByteStr, &ByteStr, Option<u64>) -> io::Result<()>,
+{
+    let (key, value, expires) = row.into_kv().map_err(|err| at_line(line, err))?;
+    put(&key, &value, expires)
+}
+
+fn at_line<E: fmt::Display>(line: u64, err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", line, err))
+}
@@ -0,0 +1,200 @@
+// 注入故障的后端：包装另一个后端，按设置让写入只写一半、fsync 失败、读到翻转的位
+//
+// 它还记录每个段最近一次 sync 成功时的长度，crash 把所有段截断到这个长度，
+// 模拟断电后没有落盘的数据全部丢失；之后用同一个后端重新打开存储，就能检查恢复的结果
+// 辅助数据（hint 等）原样交给内层后端，它们总是先落盘再替换
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::storage::{Segment, Storage};
+use crate::{offset_of, segment_of, ByteStr};
+
+#[derive(Debug, Default)]
+struct Faults {
+    // 下一次追加最多写入这么多字节，然后返回错误
+    short_write: Option<usize>,
+    fail_sync: bool,
+    // 读取时翻转的位：位置（见 make_position）和位的序号
+    flips: Vec<(u64, u8)>,
+    // 段号 -> 最近一次 sync 成功时的长度
+    synced: HashMap<u32, u64>,
+    syncs: u64,
+}
+
+#[derive(Debug)]
+pub struct FaultyStorage {
+    inner: Arc<dyn Storage>,
+    faults: Arc<Mutex<Faults>>,
+}
+
+impl FaultyStorage {
+    pub fn new(inner: Arc<dyn Storage>) -> Self {
+        FaultyStorage { inner, faults: Arc::default() }
+    }
+
+    // 下一次追加只写入前 len 个字节就失败，像写到一半时磁盘满了
+    pub fn short_write(&self, len: usize) {
+        self.faults().short_write = Some(len);
+    }
+
+    // 设置之后的 sync 是否失败
+    pub fn fail_sync(&self, fail: bool) {
+        self.faults().fail_sync = fail;
+    }
+
+    // 之后读到 position 处的字节时翻转它的第 bit 位，不改变保存的数据
+    pub fn flip_bit(&self, position: u64, bit: u8) {
+        self.faults().flips.push((position, bit % 8));
+    }
+
+    // 成功的 sync 次数
+    pub fn syncs(&self) -> u64 {
+        self.faults().syncs
+    }
+
+    // 模拟断电：把所有段截断到最近一次 sync 成功时的长度
+    // 调用之前要先丢弃使用这个后端的 ActionKV
+    pub fn crash(&self) -> io::Result<()> {
+        let synced: Vec<(u32, u64)> = self.faults().synced.drain().collect();
+        for (id, len) in synced {
+            if let Some(segment) = self.inner.open_existing(id)? {
+                if segment.len()? > len {
+                    segment.truncate(len)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn faults(&self) -> MutexGuard<'_, Faults> {
+        self.faults.lock().expect("faults poisoned")
+    }
+
+    fn wrap(&self, id: u32, inner: Arc<dyn Segment>) -> Arc<dyn Segment> {
+        Arc::new(FaultySegment { id, inner, faults: self.faults.clone() })
+    }
+}
+
+impl Storage for FaultyStorage {
+    fn segment_ids(&self) -> io::Result<Vec<u32>> {
+        self.inner.segment_ids()
+    }
+
+    fn open(&self, id: u32) -> io::Result<Arc<dyn Segment>> {
+        let segment = self.inner.open(id)?;
+        // 打开之前已有的数据当作已经落盘
+        let len = segment.len()?;
+        self.faults().synced.entry(id).or_insert(len);
+        Ok(self.wrap(id, segment))
+    }
+
+    fn open_existing(&self, id: u32) -> io::Result<Option<Arc<dyn Segment>>> {
+        Ok(self.inner.open_existing(id)?.map(|segment| self.wrap(id, segment)))
+    }
+
+    // 暂存段的 sync 不记录长度，commit 之前它们还不属于日志
+    fn stage(&self, id: u32) -> io::Result<Arc<dyn Segment>> {
+        let segment = self.inner.stage(id)?;
+        Ok(Arc::new(FaultySegment { id: u32::MAX, inner: segment, faults: self.faults.clone() }))
+    }
+
+    fn commit(&self, staged: &[u32], removed: &[u32]) -> io::Result<()> {
+        self.inner.commit(staged, removed)?;
+        for &id in removed {
+            self.faults().synced.remove(&id);
+        }
+        // 压缩在 commit 之前已经把暂存段落盘
+        for &id in staged {
+            if let Some(segment) = self.inner.open_existing(id)? {
+                let len = segment.len()?;
+                self.faults().synced.insert(id, len);
+            }
+        }
+        Ok(())
+    }
+
+    fn read_sidecar(&self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        self.inner.read_sidecar(name)
+    }
+
+    fn write_sidecar(&self, name: &str, data: &ByteStr) -> io::Result<()> {
+        self.inner.write_sidecar(name, data)
+    }
+
+    fn remove_sidecar(&self, name: &str) -> io::Result<()> {
+        self.inner.remove_sidecar(name)
+    }
+
+    fn sidecar_path(&self, name: &str) -> Option<std::path::PathBuf> {
+        self.inner.sidecar_path(name)
+    }
+}
+
+#[derive(Debug)]
+struct FaultySegment {
+    // 暂存段为 u32::MAX
+    id: u32,
+    inner: Arc<dyn Segment>,
+    faults: Arc<Mutex<Faults>>,
+}
+
+impl FaultySegment {
+    fn faults(&self) -> MutexGuard<'_, Faults> {
+        self.faults.lock().expect("faults poisoned")
+    }
+}
+
+impl Segment for FaultySegment {
+    fn len(&self) -> io::Result<u64> {
+        self.inner.len()
+    }
+
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let n = self.inner.read_at(buf, offset)?;
+        for &(position, bit) in &self.faults().flips {
+            if segment_of(position) != self.id {
+                continue;
+            }
+            let at = offset_of(position);
+            if at >= offset && at < offset + n as u64 {
+                buf[(at - offset) as usize] ^= 1 << bit;
+            }
+        }
+        Ok(n)
+    }
+
+    fn append(&self, buf: &ByteStr) -> io::Result<usize> {
+        let short = self.faults().short_write.take();
+        match short {
+            Some(len) => {
+                self.inner.append(&buf[..len.min(buf.len())])?;
+                Err(io::Error::other("injected short write"))
+            }
+            None => self.inner.append(buf),
+        }
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        if self.faults().fail_sync {
+            return Err(io::Error::other("injected fsync failure"));
+        }
+        self.inner.sync()?;
+        let len = self.inner.len()?;
+        let mut faults = self.faults();
+        faults.syncs += 1;
+        if self.id != u32::MAX {
+            faults.synced.insert(self.id, len);
+        }
+        Ok(())
+    }
+
+    fn truncate(&self, len: u64) -> io::Result<()> {
+        self.inner.truncate(len)
+    }
+
+    fn identity(&self) -> io::Result<(u64, u64)> {
+        self.inner.identity()
+    }
+}
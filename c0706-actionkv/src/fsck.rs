@@ -0,0 +1,114 @@
+// 检查整个日志：逐条校验 CRC，统计损坏、残缺的尾部、重复和已经无用的记录
+// 没有加密的压缩记录还要试着解压，解压失败的记录和无效的记录一样报告为损坏
+// 不使用 hint，也不修改文件，可以对出问题的存储随时运行
+
+use std::collections::HashMap;
+use std::io;
+
+use serde_derive::Serialize;
+
+use crate::compress;
+use crate::log::{self, Log};
+use crate::namespace::CATALOG;
+use crate::record::{CODEC_MASK, FLAG_ENCRYPTED};
+use crate::{ByteString, Corruption, LoadReport, Recovery};
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct FsckReport {
+    // 日志末尾的位置和所有段的总字节数
+    pub end: u64,
+    pub bytes: u64,
+    // 通过 CRC 校验的记录数，包括批次标记
+    pub records: u64,
+    pub corrupt: Vec<Corruption>,
+    // 尾部残缺（写了一半）或损坏的记录的位置
+    pub torn_tail: Option<u64>,
+    pub incomplete_batches: u64,
+    // 最终仍然有效的 key 和它们最新记录的字节数，不包括命名空间目录
+    pub live_keys: u64,
+    pub live_bytes: u64,
+    // 命名空间目录中的条目，也就是命名空间的个数，见 namespace.rs
+    pub namespaces: u64,
+    // 被同一个 key 之后的写入覆盖的记录
+    pub duplicates: u64,
+    pub tombstones: u64,
+    // 最新记录已经过期的 key
+    pub expired: u64,
+    // 压缩可以回收的记录和字节：除最新的有效记录以外的全部
+    pub dead_records: u64,
+    pub dead_bytes: u64,
+}
+
+impl FsckReport {
+    // 没有损坏的记录，也没有残缺的尾部
+    pub fn is_clean(&self) -> bool {
+        self.corrupt.is_empty() && self.torn_tail.is_none()
+    }
+}
+
+// 每个 key 最新的记录：长度、是否是墓碑、过期时间
+struct Latest {
+    len: u64,
+    tombstone: bool,
+    expires: Option<u64>,
+}
+
+pub(crate) fn check(log: &Log, now: u64) -> io::Result<FsckReport> {
+    let mut load = LoadReport::default();
+    // 不同命名空间中相同的 key 是不同的 key
+    let mut latest: HashMap<(u32, ByteString), Latest> = HashMap::new();
+    let mut report = FsckReport::default();
+    let mut catalog_bytes = 0;
+
+    let mut invalid = Vec::new();
+
+    log.scan(0, Recovery::Skip, &mut load, |position, record| {
+        // 加密记录的压缩数据没有密钥无法检查
+        let compressed = record.flags & FLAG_ENCRYPTED == 0 && record.flags & CODEC_MASK != 0;
+        if compressed && compress::decompress(record.flags, record.value.clone()).is_err() {
+            invalid.push(log::invalid(position, record.len(), record.checksum));
+            return;
+        }
+        if record.is_tombstone() {
+            report.tombstones += 1;
+        }
+        let entry = Latest {
+            len: record.len(),
+            tombstone: record.is_tombstone(),
+            expires: record.expires,
+        };
+        if latest.insert((record.namespace, record.key), entry).is_some() {
+            report.duplicates += 1;
+        }
+    })?;
+
+    for ((namespace, _), entry) in &latest {
+        if entry.tombstone {
+            continue;
+        }
+        // 目录条目不是用户的 key，但压缩也会保留它们
+        if *namespace == CATALOG {
+            report.namespaces += 1;
+            catalog_bytes += entry.len;
+            continue;
+        }
+        if entry.expires.is_some_and(|at| at <= now) {
+            report.expired += 1;
+            continue;
+        }
+        report.live_keys += 1;
+        report.live_bytes += entry.len;
+    }
+
+    report.end = log.end()?;
+    report.bytes = log.size()?;
+    report.records = load.records - invalid.len() as u64;
+    report.corrupt = load.corrupt;
+    report.corrupt.extend(invalid);
+    report.corrupt.sort_by_key(|corruption| corruption.offset);
+    report.torn_tail = load.torn_tail;
+    report.incomplete_batches = load.incomplete_batches;
+    report.dead_records = report.records - report.live_keys - report.namespaces;
+    report.dead_bytes = report.bytes - report.live_bytes - catalog_bytes;
+    Ok(report)
+}
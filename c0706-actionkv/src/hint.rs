@@ -0,0 +1,131 @@
+// hint 文件：index 的快照，load 时不必读取每一条记录的 value
+//
+// 存储格式
+// ┌───────┬─────────┬─────┬──────┬──────────┬───────┬─────────┬──────────┐
+// │ magic │ version │ end │ tail │ sequence │ count │ entries │ checksum │
+// ├───────┼─────────┼─────┼──────┼──────────┼───────┼─────────┼──────────┤
+// │ AKVH  │ u32     │ u64 │ u32  │ u64      │ u64   │ ...     │ u32      │
+// └───────┴─────────┴─────┴──────┴──────────┴───────┴─────────┴──────────┘
+//
+// 每个 entry：namespace u32 | key_len u32 | position u64 | size u64 | expires u64 | key
+// namespace 是命名空间 id，0 是默认命名空间；expires 为 0 表示没有过期时间
+// end 是写 hint 时日志末尾的位置（单文件存储就是文件长度），之后追加的记录仍需扫描
+// tail 是 end 之前一小段数据的校验和，见 Log::tail_checksum
+// sequence 是写 hint 时最后分配的版本号，end 之前的墓碑可能已经被压缩掉，不能只靠扫描得到
+// 数据文件比 end 短，或者 tail 对不上，说明数据文件被截断或替换过，hint 作废
+// checksum 覆盖它之前的所有字节
+// 旧版本的 hint 读到时当作无效，完整扫描一次后重新写入
+
+use std::collections::BTreeMap;
+use std::io;
+use std::io::prelude::*;
+use std::io::Cursor;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::namespace::{Keyspace, DEFAULT};
+use crate::storage::Storage;
+use crate::{ByteStr, ByteString, Expires, Index, CRC};
+
+const MAGIC: &[u8; 4] = b"AKVH";
+const VERSION: u32 = 5;
+// hint 在 Storage 中的名字
+const NAME: &str = "hint";
+
+pub(crate) struct Hint {
+    pub end: u64,
+    pub tail: u32,
+    pub sequence: u64,
+    pub index: Index,
+    pub expires: Expires,
+    // 默认命名空间以外的命名空间
+    pub namespaces: BTreeMap<u32, Keyspace>,
+}
+
+// entries: (namespace, key, position, size, expires)
+pub(crate) fn write(
+    storage: &dyn Storage,
+    end: u64,
+    tail: u32,
+    sequence: u64,
+    entries: &[(u32, &ByteStr, u64, u64, Option<u64>)],
+) -> io::Result<()> {
+    let mut buf = ByteString::new();
+    buf.write_all(MAGIC)?;
+    buf.write_u32::<LittleEndian>(VERSION)?;
+    buf.write_u64::<LittleEndian>(end)?;
+    buf.write_u32::<LittleEndian>(tail)?;
+    buf.write_u64::<LittleEndian>(sequence)?;
+    buf.write_u64::<LittleEndian>(entries.len() as u64)?;
+    for (namespace, key, position, size, expires) in entries {
+        buf.write_u32::<LittleEndian>(*namespace)?;
+        buf.write_u32::<LittleEndian>(key.len() as u32)?;
+        buf.write_u64::<LittleEndian>(*position)?;
+        buf.write_u64::<LittleEndian>(*size)?;
+        buf.write_u64::<LittleEndian>(expires.unwrap_or(0))?;
+        buf.write_all(key)?;
+    }
+    let checksum = CRC.checksum(&buf);
+    buf.write_u32::<LittleEndian>(checksum)?;
+
+    // 原子地替换，load 不会读到写了一半的 hint
+    storage.write_sidecar(NAME, &buf)
+}
+
+// 读取 hint，不存在或无效时返回 None，由调用者退回到完整扫描
+// end 是否仍在日志之内由调用者检查
+pub(crate) fn read(storage: &dyn Storage) -> io::Result<Option<Hint>> {
+    Ok(storage.read_sidecar(NAME)?.and_then(|buf| parse(&buf)))
+}
+
+fn parse(buf: &ByteStr) -> Option<Hint> {
+    if buf.len() < 40 || &buf[..4] != MAGIC {
+        return None;
+    }
+    let (body, saved_checksum) = buf.split_at(buf.len() - 4);
+    let saved_checksum = u32::from_le_bytes(saved_checksum.try_into().ok()?);
+    if CRC.checksum(body) != saved_checksum {
+        return None;
+    }
+
+    let mut f = Cursor::new(&body[4..]);
+    if f.read_u32::<LittleEndian>().ok()? != VERSION {
+        return None;
+    }
+    let end = f.read_u64::<LittleEndian>().ok()?;
+    let tail = f.read_u32::<LittleEndian>().ok()?;
+    let sequence = f.read_u64::<LittleEndian>().ok()?;
+
+    let count = f.read_u64::<LittleEndian>().ok()?;
+    let mut index = Index::new();
+    let mut expires = Expires::new();
+    let mut namespaces = BTreeMap::new();
+    for _ in 0..count {
+        let namespace = f.read_u32::<LittleEndian>().ok()?;
+        let key_len = f.read_u32::<LittleEndian>().ok()?;
+        let position = f.read_u64::<LittleEndian>().ok()?;
+        let size = f.read_u64::<LittleEndian>().ok()?;
+        let at = f.read_u64::<LittleEndian>().ok()?;
+        if position.checked_add(size)? > end {
+            return None;
+        }
+        let mut key = vec![0; key_len as usize];
+        f.read_exact(&mut key).ok()?;
+        let (index, expires) = match namespace {
+            DEFAULT => (&mut index, &mut expires),
+            id => {
+                let space: &mut Keyspace = namespaces.entry(id).or_default();
+                (&mut space.index, &mut space.expires)
+            }
+        };
+        if at != 0 {
+            expires.insert(key.clone(), at);
+        }
+        index.insert(key, position);
+    }
+    Some(Hint { end, tail, sequence, index, expires, namespaces })
+}
+
+pub(crate) fn remove(storage: &dyn Storage) -> io::Result<()> {
+    storage.remove_sidecar(NAME)
+}
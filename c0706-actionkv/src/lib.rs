@@ -9,183 +9,989 @@
 // ├──────────┼─────────┼───────────┼───────────────┼─────────────────┤
 // │ u32      │ u32     │ u32       │ [u8; key_len] │ [u8; value_len] │
 // └──────────┴─────────┴───────────┴───────────────┴─────────────────┘
+//
+// key_len 的高 8 位是记录标志，见 record.rs
 
-use std::collections::HashMap;
-use std::fs::{File, OpenOptions};
+use std::collections::{btree_map, BTreeMap, HashMap};
+use std::fmt;
 use std::io;
-use std::io::prelude::*;
-use std::io::{BufReader, BufWriter, SeekFrom};
+use std::ops::{RangeBounds, RangeFull};
 use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
 
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use crc::{Crc, CRC_32_ISCSI};
 use serde_derive::{Deserialize, Serialize};
 
+#[cfg(feature = "async")]
+mod async_kv;
+mod backup;
+mod batch;
+mod clock;
+mod compress;
+mod crypto;
+mod dump;
+mod faulty;
+mod fsck;
+mod hint;
+mod log;
+mod memory;
+mod namespace;
+mod record;
+mod replication;
+mod resp;
+mod scan;
+mod server;
+mod shared;
+mod snapshot;
+mod storage;
+mod subscribe;
+mod typed;
+
+#[cfg(feature = "async")]
+pub use async_kv::AsyncActionKV;
+pub use backup::BackupReport;
+pub use batch::WriteBatch;
+pub use clock::{Clock, SystemClock};
+pub use compress::Compression;
+pub use crypto::EncryptionKey;
+pub use dump::DumpFormat;
+pub use faulty::FaultyStorage;
+pub use fsck::FsckReport;
+pub use memory::MemoryStorage;
+pub use namespace::{Namespace, NamespaceMut};
+pub use replication::{serve_replication, Follower};
+pub use scan::Scan;
+pub use server::{serve, serve_follower};
+pub use shared::SharedActionKV;
+pub use snapshot::Snapshot;
+pub use storage::{FileStorage, Segment, Storage};
+pub use subscribe::{Change, Subscription};
+pub use typed::{DecodeError, Encoding, TypedStore};
+
+use log::Log;
+use namespace::{Keyspace, Namespaces, CATALOG, DEFAULT};
+use record::Record;
+
 type ByteString = Vec<u8>;
 type ByteStr = [u8];
 
 pub const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISCSI);
 
+// key -> 最新记录的位置，有序，可以按范围和前缀遍历
+pub type Index = BTreeMap<ByteString, u64>;
+
+// 设置了过期时间的 key -> 过期时间，大多数 key 没有过期时间，单独存放
+type Expires = HashMap<ByteString, u64>;
+
+// 记录的位置：高 20 位是段号，低 44 位是段内偏移
+// 单文件存储只有 0 号段，位置就是文件偏移；段号递增，所以位置的大小顺序就是写入顺序
+const OFFSET_BITS: u32 = 44;
+const OFFSET_MASK: u64 = (1 << OFFSET_BITS) - 1;
+pub const MAX_SEGMENT_ID: u32 = (1 << (64 - OFFSET_BITS)) - 1;
+
+pub fn make_position(segment: u32, offset: u64) -> u64 {
+    (segment as u64) << OFFSET_BITS | offset
+}
+
+pub fn segment_of(position: u64) -> u32 {
+    (position >> OFFSET_BITS) as u32
+}
+
+pub fn offset_of(position: u64) -> u64 {
+    position & OFFSET_MASK
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct KeyValuePair {
     pub key: ByteString,
     pub value: ByteString,
+    pub tombstone: bool,
+    // 过期时间，自 UNIX 纪元起的毫秒数
+    pub expires: Option<u64>,
+    // 版本号，没有版本号的旧记录为 None
+    pub version: Option<u64>,
+}
+
+// 校验和不匹配的记录，作为 io::ErrorKind::InvalidData 错误的内部错误返回
+// 恢复时长度越过段末尾的记录也报告为 Corruption，actual 为 0；
+// 校验通过但内容无效的记录（标志组合不对、批次标记或压缩数据损坏）expected 和 actual 相同
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Corruption {
+    // 记录的位置，见 make_position
+    pub offset: u64,
+    // 整条记录（含头部）的长度，用于跳过这条记录；长度越界时是到下一条完整记录的距离
+    pub len: u64,
+    pub expected: u32,
+    pub actual: u32,
+}
+
+impl Corruption {
+    // 从 io::Error 中取出 Corruption
+    pub fn from_io_error(err: &io::Error) -> Option<&Corruption> {
+        err.get_ref()?.downcast_ref::<Corruption>()
+    }
+}
+
+impl fmt::Display for Corruption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.expected == self.actual {
+            return write!(f, "invalid record encountered at offset {}", self.offset);
+        }
+        write!(
+            f,
+            "data corruption encountered at offset {} ({:08x} != {:08x})",
+            self.offset, self.actual, self.expected
+        )
+    }
+}
+
+impl std::error::Error for Corruption {}
+
+// 条件写入时 key 的当前版本与期望的不一致，作为 io::ErrorKind::Other 错误的内部错误返回
+// 版本为 None 表示 key 不存在
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub key: ByteString,
+    pub expected: Option<u64>,
+    pub actual: Option<u64>,
+}
+
+impl Conflict {
+    // 从 io::Error 中取出 Conflict
+    pub fn from_io_error(err: &io::Error) -> Option<&Conflict> {
+        err.get_ref()?.downcast_ref::<Conflict>()
+    }
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |version: Option<u64>| version.map_or("absent".to_string(), |v| v.to_string());
+        write!(
+            f,
+            "version conflict on key {:?}: expected {}, found {}",
+            self.key,
+            show(self.expected),
+            show(self.actual)
+        )
+    }
+}
+
+impl std::error::Error for Conflict {}
+
+// load 遇到损坏记录时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Recovery {
+    // 任何损坏都返回错误
+    #[default]
+    Strict,
+    // 跳过损坏的记录，尾部的残缺记录只报告，不修改文件
+    // 有残缺尾部时拒绝写入：追加的记录会被残缺记录声明的长度盖住，需要写入时应使用 Truncate
+    Skip,
+    // 跳过损坏的记录，并把文件截断到最后一条完整记录之后
+    Truncate,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LoadReport {
+    // 成功读取的记录数
+    pub records: u64,
+    // 文件中间被跳过的损坏记录
+    pub corrupt: Vec<Corruption>,
+    // 尾部残缺（写了一半）或损坏的记录的位置
+    pub torn_tail: Option<u64>,
+    // 截断后日志末尾的位置
+    pub truncated_to: Option<u64>,
+    // index 是否从 hint 文件恢复，此时 records 只统计 hint 之后的记录
+    pub hinted: bool,
+    // 没有 commit 而被丢弃的批次
+    pub incomplete_batches: u64,
+    // 读到的记录中最大的版本号，包括墓碑和批次标记
+    pub max_version: u64,
+}
+
+// 写入何时落盘
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Durability {
+    // 每次写入后都 fsync，返回成功时数据已经落盘
+    Always,
+    // 组提交：距上次 fsync 超过 interval，或未落盘的数据达到 bytes 时 fsync
+    // 写入时检查这两个条件；存储空闲时由后台线程在最早一次未落盘的写入之后 interval 内 fsync
+    GroupCommit { interval: Duration, bytes: u64 },
+    // 只写入操作系统的缓存，断电可能丢失已经返回成功的写入
+    #[default]
+    Buffered,
+}
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    // 设置后 path 是一个目录，数据分段存放，活动段超过这个大小就换到新段
+    pub segment_size: Option<u64>,
+    pub durability: Durability,
+    // 判断 key 是否过期用的时钟
+    pub clock: Arc<dyn Clock>,
+    // 新写入的 value 默认使用的压缩方式，不影响已有的记录
+    pub compression: Compression,
+    // 设置后新写入的 value 都会加密，读取加密的 value 也需要它，见 crypto.rs
+    // 没有加密的旧记录仍然可以读取
+    pub encryption_key: Option<EncryptionKey>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            segment_size: None,
+            durability: Durability::default(),
+            clock: Arc::new(SystemClock),
+            compression: Compression::default(),
+            encryption_key: None,
+        }
+    }
+}
+
+// 从 index 中去掉已经过期的 key
+fn remove_expired(index: &mut Index, expires: &mut Expires, now: u64) {
+    expires.retain(|key, &mut at| {
+        if at <= now {
+            index.remove(key);
+        }
+        at > now
+    });
+}
+
+// 按一条已提交的记录更新 index 和 expires，已经过期的记录当作删除
+fn apply_record(index: &mut Index, expires: &mut Expires, position: u64, record: Record, now: u64) {
+    if record.is_tombstone() || record.is_expired(now) {
+        index.remove(&record.key);
+        expires.remove(&record.key);
+        return;
+    }
+    match record.expires {
+        Some(at) => expires.insert(record.key.clone(), at),
+        None => expires.remove(&record.key),
+    };
+    index.insert(record.key, position);
 }
 
 #[derive(Debug)]
 pub struct ActionKV {
-    f: File,
-    pub index: HashMap<ByteString, u64>,
+    log: Log,
+    // 日志停在一个未提交的批次中，下一次写入之前要先写 abort 标记
+    open_batch: bool,
+    // 过期的 key 在 load 或 compact 之前仍留在 index 中，读取时按 expires 过滤
+    pub index: Index,
+    expires: Expires,
+    // 默认命名空间以外的命名空间，见 namespace.rs
+    namespaces: Namespaces,
+    clock: Arc<dyn Clock>,
+    compression: Compression,
+    encryption_key: Option<EncryptionKey>,
+    // 最后分配的版本号，每写一条记录加一，见 version
+    sequence: u64,
+    // Recovery::Skip 加载时留下的残缺尾部的位置，在它被截断或压缩掉之前拒绝写入
+    torn_tail: Option<u64>,
+    // 最近一次 load 的恢复方式，压缩单个命名空间时重新扫描日志也用它
+    recovery: Recovery,
 }
 
 impl ActionKV {
     pub fn open(path: &Path) -> io::Result<Self> {
-        let f = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .append(true)
-            .open(path)?;
-        let index = HashMap::new();
-        Ok(ActionKV {f, index})
-    }
-
-    // 解析一条记录
-    fn process_record<R: Read>(f: &mut R) -> io::Result<KeyValuePair> {
-        // 以确定的方式读取磁盘数据
-        // 磁盘上 i32 的字节序可能因系统而异
-        let saved_checksum = f.read_u32::<LittleEndian>()?;
-        let key_len = f.read_u32::<LittleEndian>()?;
-        let val_len = f.read_u32::<LittleEndian>()?;
-        let data_len = key_len + val_len;
-        let mut data = ByteString::with_capacity(data_len as usize);
-        {
-            // 包在 block 中，解决ref所有权问题？
-            // 去掉后为什么没有报所有权的问题？
-            f.by_ref().take(data_len as u64).read_to_end(&mut data)?;
-        }
-        debug_assert_eq!(data.len(), data_len as usize);
-
-        // 记录的头部不参与校验
-        let checksum = CRC.checksum(&data);
-        if checksum != saved_checksum {
-            panic!("data corruption encountered ({:08x} != {:08x})", checksum, saved_checksum);
-        }
-
-        let value = data.split_off(key_len as usize);
-        let key = data;
-        Ok(KeyValuePair {key, value})
+        ActionKV::open_with(path, Options::default())
+    }
+
+    pub fn open_with(path: &Path, options: Options) -> io::Result<Self> {
+        let storage = FileStorage::open(path, options.segment_size.is_some())?;
+        ActionKV::open_with_storage(Arc::new(storage), options)
+    }
+
+    // 在给定的存储后端上打开，例如 MemoryStorage，见 storage.rs
+    // 同一个后端之前写入的数据仍然需要 load
+    pub fn open_with_storage(storage: Arc<dyn Storage>, options: Options) -> io::Result<Self> {
+        let log = Log::open(storage, &options)?;
+        Ok(ActionKV::from_log(log, options))
+    }
+
+    fn from_log(log: Log, options: Options) -> Self {
+        let index = Index::new();
+        let expires = Expires::new();
+        ActionKV {
+            log,
+            open_batch: false,
+            index,
+            expires,
+            namespaces: Namespaces::default(),
+            clock: options.clock,
+            compression: options.compression,
+            encryption_key: options.encryption_key,
+            sequence: 0,
+            torn_tail: None,
+            recovery: Recovery::Strict,
+        }
     }
 
+    // Recovery::Skip 加载后留下的残缺尾部的位置，不是 None 时写入会失败
+    pub fn torn_tail(&self) -> Option<u64> {
+        self.torn_tail
+    }
+
+    // 返回日志末尾的位置
     pub fn seek_to_end(&mut self) -> io::Result<u64> {
-        self.f.seek(SeekFrom::End(0))
+        self.log.end()
     }
 
     // 加载数据，重建 index 索引
     pub fn load(&mut self) -> io::Result<()> {
-        let mut f = BufReader::new(&mut self.f);
-
-        loop {
-            let current_position = f.seek(SeekFrom::Current(0))?;
-            let maybe_kv = ActionKV::process_record(&mut f);
-            let kv = match maybe_kv {
-                Ok(kv) => kv,
-                Err(err) => {
-                    match err.kind() {
-                        // 文件结束
-                        io::ErrorKind::UnexpectedEof => {
-                            break;
-                        }
-                        _ => return Err(err),
-                    }
+        self.load_with(Recovery::Strict).map(|_| ())
+    }
+
+    // 加载数据，按 recovery 处理损坏的记录，返回加载报告
+    // 已经过期的记录当作删除
+    pub fn load_with(&mut self, recovery: Recovery) -> io::Result<LoadReport> {
+        let mut report = LoadReport::default();
+        let mut start = 0;
+        self.index.clear();
+        self.expires.clear();
+        self.namespaces.clear();
+        self.sequence = 0;
+        // 有效的 hint 可以直接恢复 index，只需扫描之后追加的记录
+        if let Some(hint) = hint::read(self.log.storage().as_ref())? {
+            // hint 之后日志被截断或替换过，只能完整扫描
+            if self.log.tail_checksum(hint.end)? == Some(hint.tail) {
+                self.index = hint.index;
+                self.expires = hint.expires;
+                self.namespaces.set_spaces(hint.namespaces);
+                self.sequence = hint.sequence;
+                start = hint.end;
+                report.hinted = true;
+            }
+        }
+
+        let now = self.clock.now();
+        let (index, expires) = (&mut self.index, &mut self.expires);
+        let namespaces = &mut self.namespaces;
+        self.open_batch = self.log.scan(start, recovery, &mut report, |position, record| {
+            let (index, expires) = match record.namespace {
+                DEFAULT => (&mut *index, &mut *expires),
+                id => {
+                    let space = namespaces.get_mut(id);
+                    (&mut space.index, &mut space.expires)
                 }
             };
-            self.index.insert(kv.key, current_position);
-        }
+            apply_record(index, expires, position, record, now);
+        })?;
+        self.sequence = self.sequence.max(report.max_version);
+        self.torn_tail = report.torn_tail.filter(|_| report.truncated_to.is_none());
+        self.recovery = recovery;
+        self.remove_expired(now);
+        self.load_names()?;
+
+        Ok(report)
+    }
 
+    // 从目录中读出命名空间的名字和 id
+    fn load_names(&mut self) -> io::Result<()> {
+        let catalog: Vec<u64> = self.namespaces.get(CATALOG).index.values().copied().collect();
+        for position in catalog {
+            let kv = self.get_at(position)?;
+            let (name, id) = match (String::from_utf8(kv.key), <[u8; 4]>::try_from(kv.value)) {
+                (Ok(name), Ok(id)) => (name, u32::from_le_bytes(id)),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("invalid namespace entry at offset {}", position),
+                    ))
+                }
+            };
+            self.namespaces.insert_name(name, id);
+        }
         Ok(())
     }
 
-    pub fn get(&mut self, key: &ByteStr) -> io::Result<Option<ByteString>> {
+    // 从所有命名空间的 index 中去掉已经过期的 key
+    fn remove_expired(&mut self, now: u64) {
+        remove_expired(&mut self.index, &mut self.expires, now);
+        for space in self.namespaces.spaces_mut() {
+            remove_expired(&mut space.index, &mut space.expires, now);
+        }
+    }
+
+    fn is_expired(&self, key: &ByteStr, now: u64) -> bool {
+        self.expires.get(key).is_some_and(|&at| at <= now)
+    }
+
+    // 命名空间 id 的 index 和 expires
+    fn keyspace(&self, namespace: u32) -> (&Index, &Expires) {
+        match namespace {
+            DEFAULT => (&self.index, &self.expires),
+            id => {
+                let space = self.namespaces.get(id);
+                (&space.index, &space.expires)
+            }
+        }
+    }
+
+    fn keyspace_mut(&mut self, namespace: u32) -> (&mut Index, &mut Expires) {
+        match namespace {
+            DEFAULT => (&mut self.index, &mut self.expires),
+            id => {
+                let space = self.namespaces.get_mut(id);
+                (&mut space.index, &mut space.expires)
+            }
+        }
+    }
+
+    // 命名空间中 key 最新记录的位置，key 不存在或已经过期时返回 None
+    fn position_in(&self, namespace: u32, key: &ByteStr) -> Option<u64> {
+        let (index, expires) = self.keyspace(namespace);
+        let position = *index.get(key)?;
+        let expired = expires.get(key).is_some_and(|&at| at <= self.clock.now());
+        (!expired).then_some(position)
+    }
+
+    // key 存在并且没有过期
+    pub fn contains_key(&self, key: &ByteStr) -> bool {
+        self.position_in(DEFAULT, key).is_some()
+    }
+
+    // key 的过期时间，key 不存在或没有过期时间时返回 None
+    pub fn expiry(&self, key: &ByteStr) -> Option<u64> {
+        let at = *self.expires.get(key)?;
+        (at > self.clock.now()).then_some(at)
+    }
+
+    // key 当前的版本号，key 不存在或已经过期时返回 None，没有版本号的旧记录返回 0
+    // 版本号是整个存储递增的序号，每次写入（包括删除）都分配新的版本号，
+    // 删除后重新插入的 key 也不会得到用过的版本号
+    pub fn version(&self, key: &ByteStr) -> io::Result<Option<u64>> {
         let position = match self.index.get(key) {
             None => return Ok(None),
             Some(position) => *position,
         };
+        if self.is_expired(key, self.clock.now()) {
+            return Ok(None);
+        }
+        Ok(Some(self.log.read_at(position)?.version.unwrap_or(0)))
+    }
 
+    // value 和它的版本号，版本号可以交给 update_if_version 和 delete_if_version
+    pub fn get_with_version(&self, key: &ByteStr) -> io::Result<Option<(ByteString, u64)>> {
+        let position = match self.index.get(key) {
+            None => return Ok(None),
+            Some(position) => *position,
+        };
+        if self.is_expired(key, self.clock.now()) {
+            return Ok(None);
+        }
         let kv = self.get_at(position)?;
-        Ok(Some(kv.value))
-    }
-
-    pub fn get_at(&mut self, position: u64) -> io::Result<KeyValuePair> {
-        let mut f = BufReader::new(&mut self.f);
-        f.seek(SeekFrom::Start(position))?;
-        let kv = ActionKV::process_record(&mut f)?;
-        Ok(kv)
-    }
-
-    // 查找，查找与 load 差不多，需要遍历整个文件，找到最后一次的 kv
-    pub fn find(&mut self, target: &ByteStr) -> io::Result<Option<(u64, ByteString)>> {
-        let mut f = BufReader::new(&mut self.f);
-        let mut found: Option<(u64, ByteString)> = None;
-
-        loop {
-            let position = f.seek(SeekFrom::Current(0))?;
-
-            let maybe_kv = ActionKV::process_record(&mut f);
-            let kv = match maybe_kv {
-                Ok(kv) => kv,
-                Err(err) => {
-                    match err.kind() {
-                        io::ErrorKind::UnexpectedEof => {
-                            break;
-                        }
-                        _ => return Err(err),
-                    }
-                }
-            };
+        Ok(Some((kv.value, kv.version.unwrap_or(0))))
+    }
 
-            if kv.key == target {
-                found = Some((position, kv.value));
+    pub fn get(&self, key: &ByteStr) -> io::Result<Option<ByteString>> {
+        self.get_in(DEFAULT, key)
+    }
+
+    fn get_in(&self, namespace: u32, key: &ByteStr) -> io::Result<Option<ByteString>> {
+        match self.position_in(namespace, key) {
+            None => Ok(None),
+            Some(position) => Ok(Some(self.get_at(position)?.value)),
+        }
+    }
+
+    pub fn get_at(&self, position: u64) -> io::Result<KeyValuePair> {
+        self.log.read_at(position)?.into_kv(self.encryption_key.as_ref())
+    }
+
+    // 按 key 的顺序遍历 range 内的 kv
+    pub fn scan<R: RangeBounds<ByteStr>>(&self, range: R) -> Scan<'_> {
+        self.scan_in(DEFAULT, range)
+    }
+
+    // 按 key 的顺序遍历以 prefix 开头的 kv
+    pub fn prefix(&self, prefix: &ByteStr) -> Scan<'_> {
+        self.prefix_in(DEFAULT, prefix)
+    }
+
+    fn scan_in<R: RangeBounds<ByteStr>>(&self, namespace: u32, range: R) -> Scan<'_> {
+        let (index, expires) = self.keyspace(namespace);
+        self.scan_keys(index.range(range), expires)
+    }
+
+    fn prefix_in(&self, namespace: u32, prefix: &ByteStr) -> Scan<'_> {
+        let (index, expires) = self.keyspace(namespace);
+        self.scan_keys(index.range(scan::prefix_range(prefix)), expires)
+    }
+
+    fn scan_keys<'a>(
+        &'a self,
+        keys: btree_map::Range<'a, ByteString, u64>,
+        expires: &'a Expires,
+    ) -> Scan<'a> {
+        Scan {
+            segments: self.log.segments(),
+            keys,
+            expires,
+            now: self.clock.now(),
+            secret: self.encryption_key.as_ref(),
+        }
+    }
+
+    // 按顺序列出所有没有过期的 key，不读磁盘
+    pub fn keys(&self) -> impl Iterator<Item = &ByteString> {
+        self.keys_in(DEFAULT)
+    }
+
+    fn keys_in(&self, namespace: u32) -> impl Iterator<Item = &ByteString> {
+        let now = self.clock.now();
+        let (index, expires) = self.keyspace(namespace);
+        index.keys().filter(move |key| expires.get(*key).is_none_or(|&at| at > now))
+    }
+
+    // 查找已有的命名空间，不存在时返回 None，不会创建，见 namespace.rs
+    pub fn namespace(&self, name: &str) -> Option<Namespace<'_>> {
+        let id = self.namespaces.id(name)?;
+        Some(Namespace {
+            name: name.to_string(),
+            segments: self.log.segments(),
+            keyspace: self.namespaces.get(id),
+            now: self.clock.now(),
+            secret: self.encryption_key.as_ref(),
+        })
+    }
+
+    // 打开可以写入的命名空间，不存在时创建，创建会在日志中写入一条目录记录
+    pub fn create_namespace(&mut self, name: &str) -> io::Result<NamespaceMut<'_>> {
+        let id = match self.namespaces.id(name) {
+            Some(id) => id,
+            None => {
+                let id = self.namespaces.next_id()?;
+                // 目录记录不压缩也不加密：load 要读出所有名字，没有密钥或密钥不对时也不能失败
+                let version = self.next_version();
+                let record = Record::new(0, name.as_bytes(), &id.to_le_bytes())?
+                    .with_version(version)
+                    .with_namespace(CATALOG);
+                let positions = self.append(&[record])?;
+                self.keyspace_mut(CATALOG).0.insert(name.as_bytes().to_vec(), positions[0]);
+                self.namespaces.insert_name(name.to_string(), id);
+                id
+            }
+        };
+        Ok(NamespaceMut::new(self, name, id))
+    }
+
+    // 按名字的顺序列出所有命名空间，不包括默认命名空间
+    pub fn namespaces(&self) -> impl Iterator<Item = &str> {
+        self.namespaces.names()
+    }
+
+    // 快照：固定当前的日志末尾和 index，见 snapshot.rs
+    pub fn snapshot(&self) -> io::Result<Snapshot> {
+        Ok(Snapshot::new(
+            self.log.clone_segments(),
+            self.log.end()?,
+            Keyspace { index: self.index.clone(), expires: self.expires.clone() },
+            self.namespaces.clone(),
+            self.clock.now(),
+            self.encryption_key.clone(),
+            self.sequence,
+        ))
+    }
+
+    // 按 key 的顺序把没有过期的 kv 写成 JSON Lines 或 CSV，返回行数，见 dump.rs
+    pub fn export<W: io::Write>(&self, out: W, format: DumpFormat) -> io::Result<u64> {
+        dump::export(self.scan::<RangeFull>(..), &self.expires, out, format)
+    }
+
+    // 导入 export 写出的数据，已有的 key 被覆盖
+    pub fn import<R: io::Read>(&mut self, input: R, format: DumpFormat) -> io::Result<u64> {
+        dump::import(input, format, |key, value, expires| self.put(DEFAULT, key, value, expires))
+    }
+
+    // 检查整个日志，见 fsck.rs；不需要先 load，损坏的存储也能检查
+    pub fn fsck(&self) -> io::Result<FsckReport> {
+        fsck::check(&self.log, self.clock.now())
+    }
+
+    // 在线备份：把当前的快照写成一个单文件存储，dest 已存在时返回 AlreadyExists，见 backup.rs
+    pub fn backup(&self, dest: &Path) -> io::Result<BackupReport> {
+        self.snapshot()?.backup(dest)
+    }
+
+    // 从 backup 恢复到 path 并加载，path 已存在时返回 AlreadyExists
+    pub fn restore(backup: &Path, path: &Path, options: Options) -> io::Result<ActionKV> {
+        backup::restore(backup, path, options)
+    }
+
+    // 从日志位置 from 开始订阅已提交的变更，0 表示从头开始，见 subscribe.rs
+    pub fn subscribe(&self, from: u64) -> io::Result<Subscription> {
+        let from = if from == 0 { self.log.start() } else { from };
+        Ok(Subscription::new(
+            self.log.storage().clone(),
+            self.log.clone_segments(),
+            from,
+            self.encryption_key.clone(),
+        ))
+    }
+
+    // 日志写到 position 时 key 的值，只有 position 之前提交的记录生效
+    // 日志中没有写入时间，这里不判断过期；压缩会丢弃旧记录并改变位置，
+    // position 只在下一次压缩之前有意义
+    pub fn get_as_of(&self, key: &ByteStr, position: u64) -> io::Result<Option<ByteString>> {
+        let mut found: Option<Record> = None;
+        let mut report = LoadReport::default();
+
+        self.log.scan_until(0, position, Recovery::Skip, &mut report, |_, record| {
+            if record.namespace == DEFAULT && record.key == key {
+                found = (!record.is_tombstone()).then_some(record);
+            }
+        })?;
+
+        found.map(|record| record.into_value(self.encryption_key.as_ref())).transpose()
+    }
+
+    // 查找，查找与 load 差不多，需要遍历整个日志，找到最后一次的 kv
+    pub fn find(&self, target: &ByteStr) -> io::Result<Option<(u64, ByteString)>> {
+        let mut found: Option<(u64, Record)> = None;
+        let mut report = LoadReport::default();
+        let now = self.clock.now();
+
+        self.log.scan(0, Recovery::Skip, &mut report, |position, record| {
+            if record.namespace == DEFAULT && record.key == target {
+                found = if record.is_tombstone() || record.is_expired(now) {
+                    None
+                } else {
+                    Some((position, record))
+                };
+            }
+        })?;
+
+        match found {
+            None => Ok(None),
+            Some((position, record)) => {
+                let value = record.into_value(self.encryption_key.as_ref())?;
+                Ok(Some((position, value)))
             }
         }
-        
-        Ok(found)
     }
 
     // 插入
     pub fn insert(&mut self, key: &ByteStr, value:&ByteStr) -> io::Result<()> {
-        let position = self.insert_but_ignore_index(key, value)?;
+        self.put(DEFAULT, key, value, None)
+    }
+
+    // 插入一个 ttl 之后过期的 kv
+    pub fn insert_with_ttl(
+        &mut self,
+        key: &ByteStr,
+        value: &ByteStr,
+        ttl: Duration,
+    ) -> io::Result<()> {
+        self.insert_expiring_at(key, value, self.expires_after(ttl))
+    }
+
+    // 从现在起 ttl 之后的过期时间，太大的 ttl 当作永不过期
+    fn expires_after(&self, ttl: Duration) -> u64 {
+        let ttl = u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX);
+        self.clock.now().saturating_add(ttl)
+    }
+
+    // 插入一个在 expires（自 UNIX 纪元起的毫秒数）过期的 kv
+    pub fn insert_expiring_at(
+        &mut self,
+        key: &ByteStr,
+        value: &ByteStr,
+        expires: u64,
+    ) -> io::Result<()> {
+        self.put(DEFAULT, key, value, Some(expires))
+    }
+
+    // 写入命名空间，expires 为 None 时 key 不再有过期时间
+    fn put(
+        &mut self,
+        namespace: u32,
+        key: &ByteStr,
+        value: &ByteStr,
+        expires: Option<u64>,
+    ) -> io::Result<()> {
+        let version = self.next_version();
+        let record = self.encode(namespace, key, value, self.compression, expires, version)?;
+        let positions = self.append(&[record])?;
+        let (index, expiries) = self.keyspace_mut(namespace);
         // 内存中记录最后的key的文件偏移
-        self.index.insert(key.to_vec(), position);
+        index.insert(key.to_vec(), positions[0]);
+        match expires {
+            Some(at) => expiries.insert(key.to_vec(), at),
+            None => expiries.remove(key),
+        };
         Ok(())
     }
 
     pub fn insert_but_ignore_index(&mut self, key: &ByteStr, value:&ByteStr) -> io::Result<u64> {
-        // append only
+        let version = self.next_version();
+        let record = self.encode(DEFAULT, key, value, self.compression, None, version)?;
+        let positions = self.append(&[record])?;
+        Ok(positions[0])
+    }
+
+    // 插入，这一条记录使用 compression 而不是存储默认的压缩方式
+    pub fn insert_compressed(
+        &mut self,
+        key: &ByteStr,
+        value: &ByteStr,
+        compression: Compression,
+    ) -> io::Result<()> {
+        let version = self.next_version();
+        let record = self.encode(DEFAULT, key, value, compression, None, version)?;
+        let positions = self.append(&[record])?;
+        self.index.insert(key.to_vec(), positions[0]);
+        self.expires.remove(key);
+        Ok(())
+    }
+
+    // 分配一个新的版本号
+    fn next_version(&mut self) -> u64 {
+        self.sequence += 1;
+        self.sequence
+    }
+
+    // 编码一条 insert 记录：先压缩，再设置过期时间、版本号和命名空间，最后加密
+    fn encode(
+        &self,
+        namespace: u32,
+        key: &ByteStr,
+        value: &ByteStr,
+        compression: Compression,
+        expires: Option<u64>,
+        version: u64,
+    ) -> io::Result<Record> {
+        let mut record = Record::new(0, key, value)?.compressed(compression)?;
+        if let Some(expires) = expires {
+            record = record.with_expiry(expires);
+        }
+        record = record.with_version(version).with_namespace(namespace);
+        match &self.encryption_key {
+            Some(secret) => record.encrypted(secret),
+            None => Ok(record),
+        }
+    }
+
+    // 残缺尾部之后追加的记录，以及覆盖到它后面的 hint，都会被残缺记录声明的长度盖住
+    fn check_torn_tail(&self) -> io::Result<()> {
+        match self.torn_tail {
+            Some(position) => Err(io::Error::other(format!(
+                "torn record at offset {}, load with Recovery::Truncate before writing",
+                position
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    // 一次写入多条记录，返回每条记录的位置
+    // append only
+    fn append(&mut self, records: &[Record]) -> io::Result<Vec<u64>> {
+        self.check_torn_tail()?;
+        let mut buf = ByteString::new();
+        if self.open_batch {
+            batch::abort_marker()?.write(&mut buf)?;
+        }
+        let mut offsets = Vec::with_capacity(records.len());
+        for record in records {
+            offsets.push(buf.len() as u64);
+            record.write(&mut buf)?;
+        }
+
+        let start = self.log.append(&buf)?;
+        self.open_batch = false;
+        Ok(offsets.into_iter().map(|offset| start + offset).collect())
+    }
+
+    // 原子地写入一组 insert/delete，崩溃后要么全部可见，要么全部不可见
+    pub fn write_batch(&mut self, batch: &WriteBatch) -> io::Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let compression = self.compression;
+        // 每个成员一个版本号，批次写入失败时不回收
+        let mut sequence = self.sequence;
+        let records = batch.to_records(|key, value| {
+            sequence += 1;
+            match value {
+                Some(value) => self.encode(DEFAULT, key, value, compression, None, sequence),
+                None => Record::tombstone(key, sequence),
+            }
+        })?;
+        self.sequence = sequence;
+        let positions = self.append(&records)?;
+
+        // 去掉 begin 和 commit 标记
+        let members = records.into_iter().zip(positions).skip(1);
+        for (record, position) in members.take(batch.len()) {
+            self.expires.remove(&record.key);
+            if record.is_tombstone() {
+                self.index.remove(&record.key);
+            } else {
+                self.index.insert(record.key, position);
+            }
+        }
+        Ok(())
+    }
+
+    // 追加从主库复制来的一组已提交记录，多于一条时作为一个批次写入，保持主库上的原子性
+    // 记录保留主库的版本号、过期时间、压缩和加密，写入后落盘，再由调用者记下复制到的位置
+    pub(crate) fn apply_replicated(&mut self, records: Vec<Record>) -> io::Result<()> {
+        let records = match records.len() {
+            0 => return Ok(()),
+            1 => records,
+            _ => batch::wrap(records)?,
+        };
+        let positions = self.append(&records)?;
+        self.log.sync()?;
+
+        let now = self.clock.now();
+        let mut named = false;
+        for (record, position) in records.into_iter().zip(positions) {
+            if record.is_batch_marker() {
+                continue;
+            }
+            self.sequence = self.sequence.max(record.version.unwrap_or(0));
+            named |= record.namespace == CATALOG;
+            let (index, expires) = self.keyspace_mut(record.namespace);
+            apply_record(index, expires, position, record, now);
+        }
+        if named {
+            self.load_names()?;
+        }
+        Ok(())
+    }
+
+    // 压缩：只把 index 指向的最新记录写入新文件，再用 rename 原子替换旧文件
+    // 被删除的 key 不在 index 中，它们的墓碑和旧值一起被丢弃，过期的 key 也一样
+    pub fn compact(&mut self) -> io::Result<()> {
+        self.remove_expired(self.clock.now());
+        let mut positions: Vec<u64> = self.index.values().copied().collect();
+        for (_, space) in self.namespaces.spaces() {
+            positions.extend(space.index.values());
+        }
+        self.rewrite(positions)
+    }
 
-        let mut f = BufWriter::new(&mut self.f);
-        let key_len = key.len();
-        let val_len = value.len();
-        let mut tmp = ByteString::with_capacity(key_len + val_len);
+    // 只压缩一个命名空间：丢弃它的旧记录和墓碑，其他命名空间已提交的记录（包括墓碑）按原顺序保留
+    // 按 load 时的恢复方式扫描日志，load 跳过的损坏记录在新文件中也被丢弃
+    fn compact_namespace(&mut self, namespace: u32) -> io::Result<()> {
+        let now = self.clock.now();
+        let (index, expires) = self.keyspace_mut(namespace);
+        remove_expired(index, expires, now);
+        let mut positions: Vec<u64> = index.values().copied().collect();
+        let mut report = LoadReport::default();
+        self.log.scan(0, self.recovery, &mut report, |position, record| {
+            if record.namespace != namespace {
+                positions.push(position);
+            }
+        })?;
+        self.rewrite(positions)
+    }
 
-        for byte in key {
-            tmp.push(*byte);
+    // 把 positions 处的记录写入新文件替换日志，所有 index 中的位置都必须在 positions 中
+    fn rewrite(&mut self, positions: Vec<u64>) -> io::Result<()> {
+        let moved = self.log.compact(positions)?;
+        // 新文件只包含完整的记录
+        self.torn_tail = None;
+        let spaces = self.namespaces.spaces_mut().map(|space| &mut space.index);
+        for index in spaces.chain([&mut self.index]) {
+            for position in index.values_mut() {
+                *position = moved[position];
+            }
         }
+        // 新文件里只有普通记录，不会停在未提交的批次中
+        self.open_batch = false;
+        self.stamp_sequence()?;
+        self.write_hint()
+    }
+
+    // 追加一个带版本号的 abort 标记，记下目前最大的版本号
+    // 压缩和恢复会丢掉墓碑，没有 hint 时只能从剩下的记录中找最大的版本号，
+    // 不记下来的话，已删除的 key 用过的版本号可能再被分配出去
+    fn stamp_sequence(&mut self) -> io::Result<()> {
+        if self.sequence == 0 {
+            return Ok(());
+        }
+        let marker = batch::abort_marker()?.with_version(self.sequence);
+        self.append(&[marker]).map(drop)
+    }
 
-        for byte in value {
-            tmp.push(*byte);
+    // 把 index 写入 hint 文件
+    pub fn write_hint(&mut self) -> io::Result<()> {
+        self.check_torn_tail()?;
+        // hint 之后的扫描不知道前面有未提交的批次，先把它关闭
+        if self.open_batch {
+            self.append(&[])?;
         }
+        let end = self.log.end()?;
+        let tail = self.log.tail_checksum(end)?.expect("log end is inside the log");
+        let now = self.clock.now();
+        let mut entries = Vec::with_capacity(self.index.len());
+        let spaces = self.namespaces.spaces().map(|(id, space)| (id, &space.index, &space.expires));
+        for (namespace, index, expires) in spaces.chain([(DEFAULT, &self.index, &self.expires)]) {
+            for (key, &position) in index {
+                let expires = expires.get(key).copied();
+                if expires.is_some_and(|at| at <= now) {
+                    continue;
+                }
+                let size = self.log.record_len_at(position)?;
+                entries.push((namespace, key.as_slice(), position, size, expires));
+            }
+        }
+        hint::write(self.log.storage().as_ref(), end, tail, self.sequence, &entries)
+    }
+
+    // 把已写入的数据交给操作系统，不等待落盘
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.log.flush()
+    }
+
+    // 把已写入的数据落盘，不论 durability 是哪种模式
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.log.sync()
+    }
+
+    // 关闭存储并写入 hint，下次 load 不必扫描整个文件
+    pub fn close(mut self) -> io::Result<()> {
+        self.write_hint()?;
+        self.log.sync()
+    }
+
+    // key 不存在（或已经过期）时才插入，返回新的版本号，否则返回 Conflict
+    pub fn insert_if_absent(&mut self, key: &ByteStr, value: &ByteStr) -> io::Result<u64> {
+        self.check_version(key, None)?;
+        self.insert(key, value)?;
+        Ok(self.sequence)
+    }
+
+    // key 的版本号是 expected 时才写入，返回新的版本号，否则返回 Conflict
+    // 和 insert 一样，写入后 key 不再有过期时间
+    pub fn update_if_version(
+        &mut self,
+        key: &ByteStr,
+        expected: u64,
+        value: &ByteStr,
+    ) -> io::Result<u64> {
+        self.check_version(key, Some(expected))?;
+        self.insert(key, value)?;
+        Ok(self.sequence)
+    }
 
-        let checksum = CRC.checksum(&tmp);
-        let next_byte = SeekFrom::End(0);
-        let current_position = f.seek(SeekFrom::Current(0))?;
-        f.seek(next_byte)?;
-        f.write_u32::<LittleEndian>(checksum)?;
-        f.write_u32::<LittleEndian>(key_len as u32)?;
-        f.write_u32::<LittleEndian>(val_len as u32)?;
-        f.write_all(&tmp)?;
+    // key 的版本号是 expected 时才删除，否则返回 Conflict
+    pub fn delete_if_version(&mut self, key: &ByteStr, expected: u64) -> io::Result<()> {
+        self.check_version(key, Some(expected))?;
+        self.delete(key)
+    }
 
-        Ok(current_position)
+    fn check_version(&self, key: &ByteStr, expected: Option<u64>) -> io::Result<()> {
+        let actual = self.version(key)?;
+        if actual != expected {
+            let conflict = Conflict { key: key.to_vec(), expected, actual };
+            return Err(io::Error::other(conflict));
+        }
+        Ok(())
     }
 
     #[inline]
@@ -195,6 +1001,1322 @@ impl ActionKV {
 
     #[inline]
     pub fn delete(&mut self, key: &ByteStr) -> io::Result<()> {
-        self.insert(key, b"")
+        self.remove(DEFAULT, key)
+    }
+
+    fn remove(&mut self, namespace: u32, key: &ByteStr) -> io::Result<()> {
+        // 写入墓碑而不是空值，这样空值也是合法的 value
+        let version = self.next_version();
+        let record = Record::tombstone(key, version)?.with_namespace(namespace);
+        self.append(&[record])?;
+        let (index, expires) = self.keyspace_mut(namespace);
+        index.remove(key);
+        expires.remove(key);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ops::Bound;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+    use std::time::Instant;
+    use std::{env, fs, process, thread};
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("actionkv-{}-{}", process::id(), name));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    // 在临时路径上打开并加载存储，返回路径，测试结束时由测试删除
+    fn open(name: &str, options: Options) -> (PathBuf, ActionKV) {
+        let path = temp_path(name);
+        let store = reopen(&path, options);
+        (path, store)
+    }
+
+    fn reopen(path: &Path, options: Options) -> ActionKV {
+        let mut store = ActionKV::open_with(path, options).unwrap();
+        store.load().unwrap();
+        store
+    }
+
+    // 内存后端外面包一层 FaultyStorage，记录落盘的位置，并可以注入故障
+    fn faulty(options: Options) -> (ActionKV, Arc<FaultyStorage>) {
+        let faults = Arc::new(FaultyStorage::new(Arc::new(MemoryStorage::new())));
+        let mut store = ActionKV::open_with_storage(faults.clone(), options).unwrap();
+        store.load().unwrap();
+        (store, faults)
+    }
+
+    // 模拟断电：没有落盘的数据全部丢失，然后用同一个后端重新打开
+    // 断电时不会运行析构函数，所以不丢弃 store
+    fn crash(store: ActionKV, faults: &Arc<FaultyStorage>) -> ActionKV {
+        std::mem::forget(store);
+        faults.crash().unwrap();
+        let mut store = ActionKV::open_with_storage(faults.clone(), Options::default()).unwrap();
+        store.load().unwrap();
+        store
+    }
+
+    #[test]
+    fn compaction_keeps_only_live_records() {
+        let (path, mut store) = open("compact", Options::default());
+        for i in 0..10 {
+            store.insert(b"a", format!("{}", i).as_bytes()).unwrap();
+        }
+        store.insert(b"b", b"2").unwrap();
+        store.insert(b"gone", b"x").unwrap();
+        store.delete(b"gone").unwrap();
+        let before = fs::metadata(&path).unwrap().len();
+
+        store.compact().unwrap();
+        assert!(fs::metadata(&path).unwrap().len() < before);
+        assert_eq!(store.get(b"a").unwrap(), Some(b"9".to_vec()));
+        assert_eq!(store.get(b"gone").unwrap(), None);
+        // 压缩后继续写入，新记录接在压缩后的文件后面
+        store.insert(b"c", b"3").unwrap();
+
+        // 不用 hint，从压缩后的文件重新建立 index
+        hint::remove(store.log.storage().as_ref()).unwrap();
+        let store = reopen(&path, Options::default());
+        let all: Vec<_> = store.scan::<RangeFull>(..).collect::<io::Result<_>>().unwrap();
+        let expected = [(b"a", b"9"), (b"b", b"2"), (b"c", b"3")];
+        assert_eq!(all, expected.map(|(key, value)| (key.to_vec(), value.to_vec())));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn tombstones_differ_from_empty_values() {
+        let (path, mut store) = open("tombstone", Options::default());
+        store.insert(b"empty", b"").unwrap();
+        store.insert(b"gone", b"1").unwrap();
+        store.delete(b"gone").unwrap();
+        assert_eq!(store.get(b"empty").unwrap(), Some(vec![]));
+        assert_eq!(store.get(b"gone").unwrap(), None);
+
+        hint::remove(store.log.storage().as_ref()).unwrap();
+        let mut store = reopen(&path, Options::default());
+        assert_eq!(store.get(b"empty").unwrap(), Some(vec![]));
+        assert!(!store.contains_key(b"gone"));
+        store.compact().unwrap();
+        assert_eq!(store.keys().collect::<Vec<_>>(), [b"empty"]);
+        fs::remove_file(store.log.sidecar_path("hint")).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn torn_tail_recovery_modes() {
+        let (path, mut store) = open("torn-tail", Options::default());
+        store.insert(b"a", b"1").unwrap();
+        store.insert(b"b", b"2").unwrap();
+        store.insert(b"c", b"3").unwrap();
+        let tail = store.index[&b"c".to_vec()];
+        let end = store.log.end().unwrap();
+        drop(store);
+        let bytes = fs::read(&path).unwrap();
+
+        // 头部不完整，以及头部完整但数据只写了一半
+        for cut in [tail + 5, end - 3] {
+            fs::write(&path, &bytes[..cut as usize]).unwrap();
+            let mut store = ActionKV::open(&path).unwrap();
+            let err = store.load_with(Recovery::Strict).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+            let report = store.load_with(Recovery::Skip).unwrap();
+            assert_eq!((report.torn_tail, report.truncated_to), (Some(tail), None));
+            assert_eq!(report.records, 2);
+            assert!(report.corrupt.is_empty());
+            assert_eq!(fs::metadata(&path).unwrap().len(), cut);
+            assert_eq!(store.keys().collect::<Vec<_>>(), [b"a", b"b"]);
+
+            let report = store.load_with(Recovery::Truncate).unwrap();
+            assert_eq!((report.torn_tail, report.truncated_to), (Some(tail), Some(tail)));
+            assert_eq!(fs::metadata(&path).unwrap().len(), tail);
+            store.insert(b"c", b"4").unwrap();
+            let mut store = ActionKV::open(&path).unwrap();
+            assert_eq!(store.load_with(Recovery::Strict).unwrap().records, 3);
+            assert_eq!(store.get(b"c").unwrap(), Some(b"4".to_vec()));
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn skip_refuses_writes_after_a_torn_tail() {
+        let (path, mut store) = open("skip-write", Options::default());
+        store.insert(b"a", b"1").unwrap();
+        store.insert(b"b", b"2").unwrap();
+        let end = store.log.end().unwrap();
+        drop(store);
+        fs::write(&path, &fs::read(&path).unwrap()[..end as usize - 5]).unwrap();
+
+        // 写入会被残缺的 b 声明的长度盖住，所以在截断之前拒绝写入，文件保持不变
+        let mut store = ActionKV::open(&path).unwrap();
+        let tail = store.load_with(Recovery::Skip).unwrap().torn_tail;
+        assert!(tail.is_some());
+        assert_eq!(store.torn_tail(), tail);
+        assert!(store.insert(b"c", b"3").is_err());
+        assert!(store.close().is_err());
+        assert_eq!(fs::metadata(&path).unwrap().len(), end - 5);
+
+        // 压缩丢掉残缺的尾部，之后可以写入，重新打开时严格加载也能读到
+        let mut store = ActionKV::open(&path).unwrap();
+        store.load_with(Recovery::Skip).unwrap();
+        store.compact().unwrap();
+        assert_eq!(store.torn_tail(), None);
+        store.insert(b"c", b"3").unwrap();
+        store.sync().unwrap();
+        hint::remove(store.log.storage().as_ref()).unwrap();
+        let store = reopen(&path, Options::default());
+        assert_eq!(store.keys().collect::<Vec<_>>(), [b"a", b"c"]);
+        assert_eq!(store.get(b"c").unwrap(), Some(b"3".to_vec()));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn corrupt_length_in_the_middle_is_skipped() {
+        let (path, mut store) = open("corrupt-length", Options::default());
+        store.insert(b"a", b"1").unwrap();
+        store.insert(b"b", b"2").unwrap();
+        store.insert(b"c", b"3").unwrap();
+        let damaged = store.index[&b"b".to_vec()];
+        let len = store.log.record_len_at(damaged).unwrap();
+        drop(store);
+
+        // b 的 value_len 变得很大，记录越过了文件末尾
+        let mut bytes = fs::read(&path).unwrap();
+        let field = damaged as usize + 8;
+        bytes[field..field + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        fs::write(&path, &bytes).unwrap();
+
+        let mut store = ActionKV::open(&path).unwrap();
+        assert!(store.load_with(Recovery::Strict).is_err());
+        for recovery in [Recovery::Skip, Recovery::Truncate] {
+            let report = store.load_with(recovery).unwrap();
+            assert_eq!(report.torn_tail, None, "{:?}", recovery);
+            assert_eq!(report.corrupt.len(), 1);
+            assert_eq!((report.corrupt[0].offset, report.corrupt[0].len), (damaged, len));
+            assert_eq!(store.keys().collect::<Vec<_>>(), [b"a", b"c"]);
+            assert_eq!(store.get(b"c").unwrap(), Some(b"3".to_vec()));
+        }
+        assert_eq!(fs::metadata(&path).unwrap().len(), bytes.len() as u64);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn hint_is_dropped_when_the_log_changes() {
+        let (path, mut store) = open("hint", Options::default());
+        store.insert(b"a", b"1").unwrap();
+        let short = store.log.end().unwrap();
+        store.insert(b"b", b"2").unwrap();
+        let hint = store.log.sidecar_path("hint");
+        store.close().unwrap();
+
+        let mut store = ActionKV::open(&path).unwrap();
+        let report = store.load_with(Recovery::Strict).unwrap();
+        assert_eq!((report.hinted, report.records), (true, 0));
+        // hint 之后追加的记录仍然要扫描
+        store.insert(b"c", b"3").unwrap();
+        let report = store.load_with(Recovery::Strict).unwrap();
+        assert_eq!((report.hinted, report.records), (true, 1));
+        assert_eq!(store.keys().count(), 3);
+        drop(store);
+        let bytes = fs::read(&path).unwrap();
+
+        // 日志被截断到 hint 的末尾之前
+        fs::write(&path, &bytes[..short as usize]).unwrap();
+        let mut store = ActionKV::open(&path).unwrap();
+        assert!(!store.load_with(Recovery::Strict).unwrap().hinted);
+        assert_eq!(store.keys().collect::<Vec<_>>(), [b"a"]);
+
+        // 长度不变，但 hint 末尾之前的内容被替换
+        let (other, mut replaced) = open("hint-replaced", Options::default());
+        for (key, value) in [(b"a", b"1"), (b"b", b"9"), (b"c", b"3")] {
+            replaced.insert(key, value).unwrap();
+        }
+        drop(replaced);
+        fs::rename(&other, &path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), bytes.len() as u64);
+        let mut store = ActionKV::open(&path).unwrap();
+        assert!(!store.load_with(Recovery::Strict).unwrap().hinted);
+        assert_eq!(store.get(b"b").unwrap(), Some(b"9".to_vec()));
+
+        // 损坏的 hint 被忽略
+        store.close().unwrap();
+        let mut damaged = fs::read(&hint).unwrap();
+        *damaged.last_mut().unwrap() ^= 1;
+        fs::write(&hint, damaged).unwrap();
+        let mut store = ActionKV::open(&path).unwrap();
+        assert!(!store.load_with(Recovery::Strict).unwrap().hinted);
+        assert_eq!(store.keys().count(), 3);
+        fs::remove_file(&hint).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    // 分段存储目录中的段文件，按段号排序
+    fn segment_files(path: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(path)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.ends_with(".akv"))
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn segments_roll_over_and_compact() {
+        let _ = fs::remove_dir_all(temp_path("segments"));
+        let options = Options { segment_size: Some(100), ..Options::default() };
+        let (path, mut store) = open("segments", options.clone());
+        // 每条记录 27 字节，写满 4 条（108 字节）时换段
+        for i in 0..10 {
+            store.insert(format!("k{}", i).as_bytes(), b"value").unwrap();
+        }
+        store.insert(b"k0", b"new").unwrap();
+        let files = segment_files(&path);
+        assert_eq!(files, ["00000000.akv", "00000001.akv", "00000002.akv"]);
+        assert_eq!(fs::metadata(path.join(&files[0])).unwrap().len(), 108);
+        assert_eq!(segment_of(store.index[&b"k0".to_vec()]), 2);
+        assert_eq!(store.get(b"k5").unwrap(), Some(b"value".to_vec()));
+        drop(store);
+
+        let mut store = reopen(&path, options.clone());
+        assert_eq!(store.keys().count(), 10);
+        assert_eq!(store.get(b"k0").unwrap(), Some(b"new".to_vec()));
+
+        // 压缩写入新的段号，旧段全部删除
+        store.compact().unwrap();
+        let compacted = segment_files(&path);
+        assert!(compacted.iter().all(|name| !files.contains(name)), "{:?}", compacted);
+        hint::remove(store.log.storage().as_ref()).unwrap();
+        let store = reopen(&path, options);
+        assert_eq!(store.keys().count(), 10);
+        assert_eq!(store.get(b"k0").unwrap(), Some(b"new".to_vec()));
+        assert_eq!(store.get(b"k9").unwrap(), Some(b"value".to_vec()));
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn batches_apply_only_when_committed() {
+        let (path, mut store) = open("batch", Options::default());
+        store.insert(b"a", b"1").unwrap();
+        let mut batch = WriteBatch::new();
+        batch.insert(b"a", b"2").insert(b"b", b"3").delete(b"a").insert(b"c", b"4");
+        store.write_batch(&batch).unwrap();
+        assert_eq!(store.keys().collect::<Vec<_>>(), [b"b", b"c"]);
+
+        // 崩溃时批次只写到一半：有 begin 和成员，没有 commit
+        let mut batch = WriteBatch::new();
+        batch.insert(b"b", b"5").insert(b"d", b"6");
+        let records = batch.to_records(|key, value| Record::new(0, key, value.unwrap())).unwrap();
+        store.append(&records[..records.len() - 1]).unwrap();
+        drop(store);
+
+        let mut store = ActionKV::open(&path).unwrap();
+        let report = store.load_with(Recovery::Strict).unwrap();
+        assert_eq!(report.incomplete_batches, 1);
+        assert_eq!(store.get(b"b").unwrap(), Some(b"3".to_vec()));
+        assert!(!store.contains_key(b"d"));
+
+        // 下一次写入前追加 abort，之后的记录不会被当成那个批次的成员
+        store.insert(b"e", b"7").unwrap();
+        let store = reopen(&path, Options::default());
+        assert_eq!(store.keys().collect::<Vec<_>>(), [b"b", b"c", b"e"]);
+        assert_eq!(store.get(b"a").unwrap(), None);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn scans_follow_key_order() {
+        let (path, mut store) = open("scan", Options::default());
+        for key in [&b"user:2"[..], b"user:10", b"user;", b"us", b"a", b"\xff", b"\xff\xff"] {
+            store.insert(key, &[key, b"!"].concat()).unwrap();
+        }
+        store.delete(b"user;").unwrap();
+        let keys = |scan: Scan| -> Vec<ByteString> { scan.map(|kv| kv.unwrap().0).collect() };
+
+        let sorted = [&b"a"[..], b"us", b"user:10", b"user:2", b"\xff", b"\xff\xff"];
+        assert_eq!(store.keys().collect::<Vec<_>>(), sorted);
+        assert_eq!(keys(store.prefix(b"")), sorted);
+        assert_eq!(keys(store.prefix(b"user:")), [&b"user:10"[..], b"user:2"]);
+        assert_eq!(keys(store.prefix(b"us")).len(), 3);
+        // 前缀全是 0xff 时范围没有上界
+        assert_eq!(keys(store.prefix(b"\xff")), [&b"\xff"[..], b"\xff\xff"]);
+
+        let range = (Bound::Included(&b"b"[..]), Bound::Excluded(&b"user:3"[..]));
+        assert_eq!(keys(store.scan(range)), [&b"us"[..], b"user:10", b"user:2"]);
+        let from = (Bound::Excluded(&b"user:10"[..]), Bound::Unbounded);
+        let (key, value) = store.scan(from).next().unwrap().unwrap();
+        assert_eq!((key, value), (b"user:2".to_vec(), b"user:2!".to_vec()));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn always_syncs_every_write() {
+        let options = Options { durability: Durability::Always, ..Options::default() };
+        let (mut store, faults) = faulty(options);
+        store.insert(b"a", b"1").unwrap();
+        store.insert(b"b", b"2").unwrap();
+        store.delete(b"a").unwrap();
+        assert_eq!(faults.syncs(), 3);
+
+        let store = crash(store, &faults);
+        assert_eq!(store.get(b"a").unwrap(), None);
+        assert_eq!(store.get(b"b").unwrap(), Some(b"2".to_vec()));
+    }
+
+    #[test]
+    fn buffered_loses_writes_after_last_sync() {
+        let options = Options { durability: Durability::Buffered, ..Options::default() };
+        let (mut store, faults) = faulty(options);
+        store.insert(b"a", b"1").unwrap();
+        store.sync().unwrap();
+        store.insert(b"b", b"2").unwrap();
+        store.flush().unwrap();
+        assert_eq!(faults.syncs(), 1);
+
+        let store = crash(store, &faults);
+        assert_eq!(store.get(b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(store.get(b"b").unwrap(), None);
+    }
+
+    #[test]
+    fn group_commit_syncs_after_bytes() {
+        let interval = Duration::from_secs(3600);
+        let durability = Durability::GroupCommit { interval, bytes: 100 };
+        let (mut store, faults) = faulty(Options { durability, ..Options::default() });
+        // 每条记录 12 + 2 + 8（版本号）+ 5 = 27 字节，第 4 条时达到 100 字节
+        for i in 0..3 {
+            store.insert(format!("k{}", i).as_bytes(), b"value").unwrap();
+        }
+        assert_eq!(faults.syncs(), 0);
+        store.insert(b"k3", b"value").unwrap();
+        assert_eq!(faults.syncs(), 1);
+        store.insert(b"k4", b"value").unwrap();
+
+        let store = crash(store, &faults);
+        assert_eq!(store.keys().count(), 4);
+        assert_eq!(store.get(b"k4").unwrap(), None);
+    }
+
+    #[test]
+    fn group_commit_syncs_after_interval() {
+        let interval = Duration::from_millis(20);
+        let durability = Durability::GroupCommit { interval, bytes: u64::MAX };
+        let (mut store, faults) = faulty(Options { durability, ..Options::default() });
+        store.insert(b"a", b"1").unwrap();
+        thread::sleep(interval);
+        store.insert(b"b", b"2").unwrap();
+        assert!(faults.syncs() >= 1);
+
+        let store = crash(store, &faults);
+        assert_eq!(store.get(b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(store.get(b"b").unwrap(), Some(b"2".to_vec()));
+    }
+
+    #[test]
+    fn group_commit_syncs_an_idle_store_within_interval() {
+        let interval = Duration::from_millis(20);
+        let durability = Durability::GroupCommit { interval, bytes: u64::MAX };
+        let (mut store, faults) = faulty(Options { durability, ..Options::default() });
+        let written = Instant::now();
+        store.insert(b"a", b"1").unwrap();
+        assert_eq!(faults.syncs(), 0);
+
+        // 之后没有写入，后台线程也会把 a 落盘
+        while faults.syncs() == 0 {
+            assert!(written.elapsed() < Duration::from_secs(5), "a was never synced");
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(written.elapsed() >= interval);
+        let store = crash(store, &faults);
+        assert_eq!(store.get(b"a").unwrap(), Some(b"1".to_vec()));
+    }
+
+    #[test]
+    fn failed_sync_is_not_acknowledged() {
+        let options = Options { durability: Durability::Always, ..Options::default() };
+        let (mut store, faults) = faulty(options);
+        faults.fail_sync(true);
+        assert!(store.insert(b"a", b"1").is_err());
+        assert_eq!(store.get(b"a").unwrap(), None);
+
+        faults.fail_sync(false);
+        store.insert(b"b", b"2").unwrap();
+
+        // 失败的写入已经从日志中去掉，不断电直接重新打开也读不到
+        drop(store);
+        let mut store = ActionKV::open_with_storage(faults.clone(), Options::default()).unwrap();
+        assert_eq!(store.load_with(Recovery::Strict).unwrap().records, 1);
+        assert_eq!(store.get(b"a").unwrap(), None);
+        assert_eq!(store.get(b"b").unwrap(), Some(b"2".to_vec()));
+    }
+
+    #[test]
+    fn group_commit_syncs_on_drop() {
+        let interval = Duration::from_secs(3600);
+        let durability = Durability::GroupCommit { interval, bytes: u64::MAX };
+        let (mut store, faults) = faulty(Options { durability, ..Options::default() });
+        store.insert(b"a", b"1").unwrap();
+        assert_eq!(faults.syncs(), 0);
+        drop(store);
+        assert_eq!(faults.syncs(), 1);
+
+        faults.crash().unwrap();
+        let mut store = ActionKV::open_with_storage(faults, Options::default()).unwrap();
+        store.load().unwrap();
+        assert_eq!(store.get(b"a").unwrap(), Some(b"1".to_vec()));
+    }
+
+    // 手动拨动的时钟
+    #[derive(Debug, Default)]
+    struct ManualClock(AtomicU64);
+
+    impl ManualClock {
+        fn set(&self, now: u64) {
+            self.0.store(now, Ordering::SeqCst);
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> u64 {
+            self.0.load(Ordering::SeqCst)
+        }
+    }
+
+    #[test]
+    fn expired_keys_are_invisible() {
+        let clock = Arc::new(ManualClock::default());
+        let options = Options { clock: clock.clone(), ..Options::default() };
+        clock.set(1_000);
+        let (path, mut store) = open("expiry", options.clone());
+        store.insert_with_ttl(b"session", b"1", Duration::from_millis(100)).unwrap();
+        store.insert(b"user", b"2").unwrap();
+
+        clock.set(1_099);
+        assert_eq!(store.get(b"session").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(store.expiry(b"session"), Some(1_100));
+        assert_eq!(store.expiry(b"user"), None);
+
+        clock.set(1_100);
+        assert_eq!(store.get(b"session").unwrap(), None);
+        assert!(!store.contains_key(b"session"));
+        assert_eq!(store.expiry(b"session"), None);
+        assert_eq!(store.find(b"session").unwrap(), None);
+        assert_eq!(store.keys().collect::<Vec<_>>(), vec![b"user"]);
+        let scanned: Vec<_> = store.scan::<std::ops::RangeFull>(..).map(Result::unwrap).collect();
+        assert_eq!(scanned, vec![(b"user".to_vec(), b"2".to_vec())]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn huge_ttl_never_expires() {
+        let clock = Arc::new(ManualClock::default());
+        clock.set(1_000);
+        let options = Options { clock: clock.clone(), ..Options::default() };
+        let (path, mut store) = open("expiry-huge", options);
+        store.insert_with_ttl(b"a", b"1", Duration::MAX).unwrap();
+        let mut users = store.create_namespace("users").unwrap();
+        users.insert_with_ttl(b"b", b"2", Duration::MAX).unwrap();
+        clock.set(u64::MAX - 1);
+        assert_eq!(store.expiry(b"a"), Some(u64::MAX));
+        assert_eq!(store.get(b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(store.namespace("users").unwrap().get(b"b").unwrap(), Some(b"2".to_vec()));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn overwrite_clears_expiry() {
+        let clock = Arc::new(ManualClock::default());
+        let options = Options { clock: clock.clone(), ..Options::default() };
+        let (path, mut store) = open("expiry-overwrite", options.clone());
+        store.insert_expiring_at(b"a", b"1", 10).unwrap();
+        store.insert(b"a", b"2").unwrap();
+        clock.set(20);
+        assert_eq!(store.get(b"a").unwrap(), Some(b"2".to_vec()));
+
+        let store = reopen(&path, options.clone());
+        assert_eq!(store.get(b"a").unwrap(), Some(b"2".to_vec()));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn load_drops_expired_keys() {
+        let clock = Arc::new(ManualClock::default());
+        let options = Options { clock: clock.clone(), ..Options::default() };
+        let (path, mut store) = open("expiry-load", options.clone());
+        store.insert_expiring_at(b"a", b"1", 10).unwrap();
+        store.insert_expiring_at(b"b", b"2", 20).unwrap();
+        // 一个 key 的过期时间从 hint 恢复，另一个从 hint 之后的记录恢复
+        store.close().unwrap();
+        let mut store = reopen(&path, options.clone());
+        store.insert_expiring_at(b"c", b"3", 20).unwrap();
+        drop(store);
+
+        clock.set(10);
+        let store = reopen(&path, options.clone());
+        assert_eq!(store.index.keys().collect::<Vec<_>>(), vec![b"b", b"c"]);
+        assert_eq!(store.expiry(b"b"), Some(20));
+        assert_eq!(store.expiry(b"c"), Some(20));
+
+        clock.set(20);
+        let store = reopen(&path, options.clone());
+        assert!(store.index.is_empty());
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(store.log.sidecar_path("hint")).unwrap();
+    }
+
+    #[test]
+    fn compaction_drops_expired_keys() {
+        let clock = Arc::new(ManualClock::default());
+        let options = Options { clock: clock.clone(), ..Options::default() };
+        let (path, mut store) = open("expiry-compact", options.clone());
+        store.insert_expiring_at(b"a", b"1", 10).unwrap();
+        store.insert_expiring_at(b"b", b"2", 30).unwrap();
+        clock.set(20);
+        store.compact().unwrap();
+        assert_eq!(store.index.keys().collect::<Vec<_>>(), vec![b"b"]);
+        let hint = store.log.sidecar_path("hint");
+        drop(store);
+
+        // 不用 hint，时钟回拨之后，已经被压缩掉的 key 也不会复活
+        fs::remove_file(hint).unwrap();
+        clock.set(0);
+        let store = reopen(&path, options.clone());
+        assert!(!store.contains_key(b"a"));
+        assert_eq!(store.get(b"b").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(store.expiry(b"b"), Some(30));
+        fs::remove_file(&path).unwrap();
+    }
+
+    fn json_blob() -> ByteString {
+        let item = r#"{"id": 42, "name": "actionkv", "tags": ["kv", "bitcask"]}"#;
+        format!("[{}]", vec![item; 50].join(",")).into_bytes()
+    }
+
+    #[test]
+    fn compressed_values_round_trip() {
+        for compression in [Compression::Lz4, Compression::Zstd { level: 3 }] {
+            let options = Options { compression, ..Options::default() };
+            let (path, mut store) = open("compression", options);
+            let blob = json_blob();
+            store.insert(b"blob", &blob).unwrap();
+            // 太短，压缩后不会变小，原样保存
+            store.insert(b"short", b"x").unwrap();
+            let mut batch = WriteBatch::new();
+            batch.insert(b"batched", &blob);
+            store.write_batch(&batch).unwrap();
+            assert!(store.log.end().unwrap() < blob.len() as u64, "{:?}", compression);
+
+            let record = store.log.read_at(store.index[&b"short".to_vec()]).unwrap();
+            assert_eq!(record.flags & record::CODEC_MASK, 0);
+
+            store.compact().unwrap();
+            drop(store);
+            let store = reopen(&path, Options::default());
+            assert_eq!(store.get(b"blob").unwrap(), Some(blob.clone()));
+            assert_eq!(store.get(b"batched").unwrap(), Some(blob.clone()));
+            assert_eq!(store.get(b"short").unwrap(), Some(b"x".to_vec()));
+            assert_eq!(store.find(b"blob").unwrap().map(|(_, value)| value), Some(blob));
+            fs::remove_file(store.log.sidecar_path("hint")).unwrap();
+            fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn compression_per_record() {
+        let (path, mut store) = open("compression-record", Options::default());
+        let blob = json_blob();
+        store.insert(b"plain", &blob).unwrap();
+        store.insert_compressed(b"packed", &blob, Compression::Lz4).unwrap();
+        let plain = store.log.read_at(store.index[&b"plain".to_vec()]).unwrap();
+        let packed = store.log.read_at(store.index[&b"packed".to_vec()]).unwrap();
+        assert_eq!(plain.flags & record::CODEC_MASK, 0);
+        assert_eq!(packed.flags & record::CODEC_MASK, record::FLAG_LZ4);
+        assert!(packed.len() < plain.len());
+        let values: Vec<_> = store.prefix(b"p").map(|kv| kv.unwrap().1).collect();
+        assert_eq!(values, vec![blob.clone(), blob]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    // 加入记录标志之前的格式：checksum 只覆盖 key 和 value
+    fn legacy_file(name: &str, records: &[(&ByteStr, &ByteStr)]) -> PathBuf {
+        let path = temp_path(name);
+        let mut buf = ByteString::new();
+        for (key, value) in records {
+            let data = [*key, *value].concat();
+            buf.extend_from_slice(&CRC.checksum(&data).to_le_bytes());
+            buf.extend_from_slice(&(key.len() as u32).to_le_bytes());
+            buf.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            buf.extend_from_slice(&data);
+        }
+        fs::write(&path, buf).unwrap();
+        path
+    }
+
+    #[test]
+    fn files_without_flags_stay_readable() {
+        let path = legacy_file("legacy", &[(b"a", b"1"), (b"b", b"2"), (b"a", b"3")]);
+        let options = Options { compression: Compression::Lz4, ..Options::default() };
+        let store = reopen(&path, options);
+        assert_eq!(store.get(b"a").unwrap(), Some(b"3".to_vec()));
+        assert_eq!(store.get(b"b").unwrap(), Some(b"2".to_vec()));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn legacy_empty_values_are_deletes() {
+        // 旧的 delete 写入空 value
+        let path = legacy_file("legacy-delete", &[(b"a", b"1"), (b"b", b"2"), (b"a", b"")]);
+        let mut store = reopen(&path, Options::default());
+        assert_eq!(store.get(b"a").unwrap(), None);
+        assert_eq!(store.fsck().unwrap().tombstones, 1);
+
+        // 压缩后删除仍然有效，之后写入的空 value 不是删除
+        store.compact().unwrap();
+        store.insert(b"c", b"").unwrap();
+        hint::remove(store.log.storage().as_ref()).unwrap();
+        let store = reopen(&path, Options::default());
+        assert_eq!(store.get(b"a").unwrap(), None);
+        assert_eq!(store.get(b"b").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(store.get(b"c").unwrap(), Some(vec![]));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn encrypted_values_round_trip() {
+        let secret = EncryptionKey::generate();
+        let options = Options {
+            encryption_key: Some(secret.clone()),
+            compression: Compression::Lz4,
+            ..Options::default()
+        };
+        let (path, mut store) = open("encrypted", options.clone());
+        store.insert(b"token", b"customer-secret-token").unwrap();
+        store.insert(b"blob", &json_blob()).unwrap();
+        store.insert_expiring_at(b"session", b"session-secret", u64::MAX).unwrap();
+        let mut batch = WriteBatch::new();
+        batch.insert(b"batched", b"batched-secret");
+        store.write_batch(&batch).unwrap();
+        store.compact().unwrap();
+        drop(store);
+
+        let raw = fs::read(&path).unwrap();
+        for plaintext in [&b"customer-secret-token"[..], b"session-secret", b"batched-secret"] {
+            assert!(!raw.windows(plaintext.len()).any(|window| window == plaintext));
+        }
+
+        let store = reopen(&path, options);
+        assert_eq!(store.get(b"token").unwrap(), Some(b"customer-secret-token".to_vec()));
+        assert_eq!(store.get(b"blob").unwrap(), Some(json_blob()));
+        assert_eq!(store.get(b"session").unwrap(), Some(b"session-secret".to_vec()));
+        assert_eq!(store.get(b"batched").unwrap(), Some(b"batched-secret".to_vec()));
+        fs::remove_file(store.log.sidecar_path("hint")).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn wrong_or_missing_key_fails_cleanly() {
+        let encrypted = || {
+            Options { encryption_key: Some(EncryptionKey::generate()), ..Options::default() }
+        };
+        let (path, mut store) = open("encrypted-wrong-key", encrypted());
+        store.insert(b"token", b"secret").unwrap();
+        drop(store);
+
+        let store = reopen(&path, encrypted());
+        let err = store.get(b"token").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(store.scan::<std::ops::RangeFull>(..).next().unwrap().is_err());
+
+        let store = reopen(&path, Options::default());
+        assert_eq!(store.get(b"token").unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn namespaced_store_loads_with_wrong_or_missing_key() {
+        let encrypted = || {
+            Options { encryption_key: Some(EncryptionKey::generate()), ..Options::default() }
+        };
+        let (path, mut store) = open("encrypted-namespaces", encrypted());
+        store.create_namespace("users").unwrap().insert(b"token", b"secret").unwrap();
+        drop(store);
+
+        // 目录没有加密，load 成功，只有读取加密的 value 时失败
+        for options in [encrypted(), Options::default()] {
+            let store = reopen(&path, options);
+            assert_eq!(store.namespaces().collect::<Vec<_>>(), ["users"]);
+            let users = store.namespace("users").unwrap();
+            assert_eq!(users.keys().collect::<Vec<_>>(), [b"token"]);
+            assert!(users.get(b"token").is_err());
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn tampered_ciphertext_fails_cleanly() {
+        let encryption_key = Some(EncryptionKey::generate());
+        let options = Options { encryption_key, ..Options::default() };
+        let (path, mut store) = open("encrypted-tampered", options);
+        store.insert(b"a", b"secret").unwrap();
+        let original = store.log.read_at(store.index[&b"a".to_vec()]).unwrap();
+
+        // 篡改密文并重新计算 checksum，CRC 校验能通过，认证不能
+        let mut tampered = original.value.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        let record = Record::new(original.flags, b"a", &tampered).unwrap();
+        let positions = store.append(&[record]).unwrap();
+        store.index.insert(b"a".to_vec(), positions[0]);
+        assert_eq!(store.get(b"a").unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        // 把 a 的密文原样挪到 b 下面也不行
+        let record = Record::new(original.flags, b"b", &original.value).unwrap();
+        let positions = store.append(&[record]).unwrap();
+        store.index.insert(b"b".to_vec(), positions[0]);
+        assert_eq!(store.get(b"b").unwrap_err().kind(), io::ErrorKind::InvalidData);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn snapshot_ignores_later_writes_and_compaction() {
+        let (path, mut store) = open("snapshot", Options::default());
+        store.insert(b"a", b"1").unwrap();
+        store.insert(b"b", b"2").unwrap();
+        let snapshot = store.snapshot().unwrap();
+        assert_eq!(snapshot.end(), store.log.end().unwrap());
+
+        store.insert(b"a", b"10").unwrap();
+        store.delete(b"b").unwrap();
+        store.insert(b"c", b"3").unwrap();
+        store.compact().unwrap();
+        store.insert(b"d", b"4").unwrap();
+
+        assert_eq!(snapshot.get(b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(snapshot.get(b"b").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(snapshot.get(b"c").unwrap(), None);
+        assert!(!snapshot.contains_key(b"d"));
+        let all: Vec<_> = snapshot.iter().collect::<io::Result<_>>().unwrap();
+        assert_eq!(all, vec![(b"a".to_vec(), b"1".to_vec()), (b"b".to_vec(), b"2".to_vec())]);
+
+        // 快照不借用 store，可以在别的线程遍历
+        let keys = thread::spawn(move || snapshot.keys().cloned().collect::<Vec<_>>());
+        assert_eq!(keys.join().unwrap(), vec![b"a".to_vec(), b"b".to_vec()]);
+        assert_eq!(store.get(b"a").unwrap(), Some(b"10".to_vec()));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn get_as_of_reads_earlier_values() {
+        let (path, mut store) = open("as-of", Options::default());
+        let empty = store.log.end().unwrap();
+        store.insert(b"k", b"v1").unwrap();
+        let first = store.log.end().unwrap();
+        store.insert(b"k", b"v2").unwrap();
+        let second = store.log.end().unwrap();
+        store.delete(b"k").unwrap();
+        let deleted = store.log.end().unwrap();
+        let mut batch = WriteBatch::new();
+        batch.insert(b"k", b"v3");
+        store.write_batch(&batch).unwrap();
+        let committed = store.log.end().unwrap();
+
+        assert_eq!(store.get_as_of(b"k", empty).unwrap(), None);
+        assert_eq!(store.get_as_of(b"k", first).unwrap(), Some(b"v1".to_vec()));
+        assert_eq!(store.get_as_of(b"k", second).unwrap(), Some(b"v2".to_vec()));
+        // 位置落在记录中间时，这条记录不生效
+        assert_eq!(store.get_as_of(b"k", second - 1).unwrap(), Some(b"v1".to_vec()));
+        assert_eq!(store.get_as_of(b"k", deleted).unwrap(), None);
+        // commit 标记不完整，批次没有提交
+        assert_eq!(store.get_as_of(b"k", committed - 1).unwrap(), None);
+        assert_eq!(store.get_as_of(b"k", committed).unwrap(), Some(b"v3".to_vec()));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn backup_and_restore_round_trip() {
+        let (path, dest, restored) =
+            (temp_path("backup-src"), temp_path("backup"), temp_path("backup-restored"));
+        let clock = Arc::new(ManualClock::default());
+        let options = Options { clock: clock.clone(), ..Options::default() };
+        clock.set(1_000);
+        let mut store = reopen(&path, options.clone());
+        store.insert(b"a", b"1").unwrap();
+        store.insert(b"b", b"2").unwrap();
+        store.delete(b"b").unwrap();
+        store.insert_with_ttl(b"session", b"s", Duration::from_millis(100)).unwrap();
+        store.insert_with_ttl(b"stale", b"x", Duration::from_millis(10)).unwrap();
+        clock.set(1_050);
+
+        let report = store.backup(&dest).unwrap();
+        assert_eq!(report.records, 2);
+        assert_eq!(report.end, store.log.end().unwrap());
+        // 备份之后的写入不在备份里
+        store.insert(b"c", b"3").unwrap();
+        assert_eq!(store.backup(&dest).unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+
+        let restored_store = ActionKV::restore(&dest, &restored, options.clone()).unwrap();
+        let keys: Vec<_> = restored_store.keys().cloned().collect();
+        assert_eq!(keys, [b"a".to_vec(), b"session".to_vec()]);
+        assert_eq!(restored_store.get(b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(restored_store.expiry(b"session"), Some(1_100));
+        assert!(hint::read(restored_store.log.storage().as_ref()).unwrap().is_some());
+        let err = ActionKV::restore(&dest, &restored, options).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+
+        fs::remove_file(restored_store.log.sidecar_path("hint")).unwrap();
+        for path in [&path, &dest, &restored] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn restore_rejects_corrupt_backup() {
+        let (path, dest, restored) =
+            (temp_path("corrupt-src"), temp_path("corrupt-backup"), temp_path("corrupt-restored"));
+        let mut store = reopen(&path, Options::default());
+        store.insert(b"a", b"1").unwrap();
+        store.insert(b"b", b"2").unwrap();
+        store.backup(&dest).unwrap();
+
+        let mut bytes = fs::read(&dest).unwrap();
+        *bytes.last_mut().unwrap() ^= 1;
+        fs::write(&dest, bytes).unwrap();
+        let err = ActionKV::restore(&dest, &restored, Options::default()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(!restored.exists());
+        assert!(!storage::suffixed(&restored, "restore").exists());
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&dest).unwrap();
+    }
+
+    #[test]
+    fn fsck_reports_corruption_and_dead_records() {
+        let (path, mut store) = open("fsck", Options::default());
+        store.insert(b"a", b"1").unwrap();
+        let damaged = store.log.end().unwrap();
+        store.insert(b"b", b"2").unwrap();
+        store.insert(b"a", b"3").unwrap();
+        store.insert(b"c", b"4").unwrap();
+        store.delete(b"c").unwrap();
+        let clean = store.fsck().unwrap();
+        assert!(clean.is_clean());
+        assert_eq!((clean.records, clean.live_keys, clean.duplicates), (5, 2, 2));
+        assert_eq!((clean.tombstones, clean.dead_records), (1, 3));
+
+        // 改掉 b 的 value，CRC 对不上
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[damaged as usize + 13] ^= 1;
+        fs::write(&path, bytes).unwrap();
+        let report = store.fsck().unwrap();
+        assert!(!report.is_clean());
+        assert_eq!(report.corrupt.len(), 1);
+        assert_eq!(report.corrupt[0].offset, damaged);
+        assert_eq!(report.records, 4);
+        assert_eq!(report.live_keys, 1);
+        assert_eq!(report.torn_tail, None);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn fsck_continues_past_invalid_records() {
+        let (path, mut store) = open("fsck-invalid", Options::default());
+        store.insert(b"a", b"1").unwrap();
+        // 三条 CRC 正确但无效的记录：两种压缩标志同时存在、无法解压的 LZ4、未知的批次标记
+        let both = Record::new(record::CODEC_MASK, b"x", b"1").unwrap();
+        let lz4 = Record::new(record::FLAG_LZ4, b"y", b"not lz4").unwrap();
+        let marker = Record::new(record::FLAG_BATCH, b"", &[0xee]).unwrap();
+        let positions = store.append(&[both, lz4, marker]).unwrap();
+        store.insert(b"b", b"2").unwrap();
+
+        let report = store.fsck().unwrap();
+        let offsets: Vec<u64> = report.corrupt.iter().map(|c| c.offset).collect();
+        assert_eq!(offsets, positions);
+        assert!(report.corrupt.iter().all(|c| c.expected == c.actual));
+        assert_eq!((report.records, report.live_keys), (2, 2));
+        assert_eq!(report.torn_tail, None);
+
+        // 加载时同样跳过，只有 Strict 失败
+        let mut store = ActionKV::open_with(&path, Options::default()).unwrap();
+        assert!(store.load_with(Recovery::Strict).is_err());
+        let report = store.load_with(Recovery::Skip).unwrap();
+        assert_eq!(report.corrupt.len(), 2);
+        assert_eq!(store.get(b"b").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(store.get(b"x").unwrap(), None);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn export_and_import_round_trip() {
+        let clock = Arc::new(ManualClock::default());
+        let options = Options { clock: clock.clone(), ..Options::default() };
+        clock.set(1_000);
+        for format in [DumpFormat::JsonLines, DumpFormat::Csv] {
+            let (path, copy) = (temp_path("export-src"), temp_path("export-copy"));
+            let mut store = reopen(&path, options.clone());
+            store.insert(b"text", b"a,b\n\"c\"").unwrap();
+            store.insert(b"\xff\x00", b"\x80binary").unwrap();
+            store.insert_expiring_at(b"session", b"s", 5_000).unwrap();
+            store.insert_expiring_at(b"stale", b"x", 500).unwrap();
+            store.insert(b"gone", b"1").unwrap();
+            store.delete(b"gone").unwrap();
+
+            let mut out = Vec::new();
+            assert_eq!(store.export(&mut out, format).unwrap(), 3);
+            let mut imported = reopen(&copy, options.clone());
+            assert_eq!(imported.import(out.as_slice(), format).unwrap(), 3);
+            let all = |store: &ActionKV| -> Vec<_> {
+                store.scan::<std::ops::RangeFull>(..).map(Result::unwrap).collect()
+            };
+            assert_eq!(all(&imported), all(&store));
+            assert_eq!(imported.expiry(b"session"), Some(5_000));
+            fs::remove_file(&path).unwrap();
+            fs::remove_file(&copy).unwrap();
+        }
+
+        let (path, mut store) = open("import-bad", options.clone());
+        let input = concat!(
+            "{\"key\":\"a\",\"value\":\"1\"}\n",
+            "{\"key\":\"b\",\"value\":\"%\",\"value_encoding\":\"base64\"}\n",
+        );
+        let err = store.import(input.as_bytes(), DumpFormat::JsonLines).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with("line 2:"));
+        assert_eq!(store.get(b"a").unwrap(), Some(b"1".to_vec()));
+        fs::remove_file(&path).unwrap();
+    }
+
+    fn conflict(err: io::Error) -> Conflict {
+        Conflict::from_io_error(&err).expect("not a conflict").clone()
+    }
+
+    #[test]
+    fn conditional_writes_check_versions() {
+        let (path, mut store) = open("cas", Options::default());
+
+        let v1 = store.insert_if_absent(b"k", b"a").unwrap();
+        assert_eq!(store.get_with_version(b"k").unwrap(), Some((b"a".to_vec(), v1)));
+        let err = conflict(store.insert_if_absent(b"k", b"b").unwrap_err());
+        assert_eq!((err.expected, err.actual), (None, Some(v1)));
+
+        let v2 = store.update_if_version(b"k", v1, b"b").unwrap();
+        assert!(v2 > v1);
+        // 另一个客户端还拿着 v1
+        let err = conflict(store.update_if_version(b"k", v1, b"c").unwrap_err());
+        assert_eq!((err.expected, err.actual), (Some(v1), Some(v2)));
+        assert_eq!(store.get(b"k").unwrap(), Some(b"b".to_vec()));
+        assert!(conflict(store.delete_if_version(b"k", v1).unwrap_err()).actual.is_some());
+
+        // 删除后重新插入，不会得到用过的版本号
+        store.delete_if_version(b"k", v2).unwrap();
+        assert_eq!(store.version(b"k").unwrap(), None);
+        let err = conflict(store.update_if_version(b"k", v2, b"d").unwrap_err());
+        assert_eq!(err.actual, None);
+        let v3 = store.insert_if_absent(b"k", b"e").unwrap();
+        assert!(v3 > v2 + 1);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn versions_survive_reload_and_compaction() {
+        let (path, mut store) = open("cas-reload", Options::default());
+        store.insert(b"a", b"1").unwrap();
+        let mut batch = WriteBatch::new();
+        batch.insert(b"b", b"2").delete(b"a");
+        store.write_batch(&batch).unwrap();
+        let b = store.version(b"b").unwrap().unwrap();
+        store.delete(b"b").unwrap();
+        store.insert(b"b", b"3").unwrap();
+        let latest = store.version(b"b").unwrap().unwrap();
+        assert!(latest > b);
+
+        // 压缩丢掉了墓碑，没有 hint 也不会回退
+        store.compact().unwrap();
+        hint::remove(store.log.storage().as_ref()).unwrap();
+        let mut store = reopen(&path, Options::default());
+        assert_eq!(store.version(b"b").unwrap(), Some(latest));
+        store.insert(b"c", b"4").unwrap();
+        assert_eq!(store.version(b"c").unwrap(), Some(latest + 1));
+
+        store.write_hint().unwrap();
+        let mut store = ActionKV::open(&path).unwrap();
+        assert!(store.load_with(Recovery::Strict).unwrap().hinted);
+        assert_eq!(store.insert_if_absent(b"d", b"5").unwrap(), latest + 2);
+        fs::remove_file(store.log.sidecar_path("hint")).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    fn changes(subscription: &mut Subscription) -> Vec<(ByteString, Option<ByteString>)> {
+        subscription.map(|change| change.unwrap()).map(|c| (c.key, c.value)).collect()
+    }
+
+    #[test]
+    fn subscription_follows_committed_changes() {
+        let (path, mut store) = open("subscribe", Options::default());
+        store.insert(b"a", b"1").unwrap();
+        let mut feed = store.subscribe(0).unwrap();
+        assert_eq!(changes(&mut feed), vec![(b"a".to_vec(), Some(b"1".to_vec()))]);
+        assert_eq!(feed.position(), store.log.end().unwrap());
+
+        // 追上末尾之后继续读到新的写入
+        store.delete(b"a").unwrap();
+        let resume = store.log.end().unwrap();
+        assert_eq!(changes(&mut feed), vec![(b"a".to_vec(), None)]);
+        assert_eq!(feed.position(), resume);
+
+        // 没有 commit 的批次不交付，位置停在批次之前
+        let mut batch = WriteBatch::new();
+        batch.insert(b"b", b"2").insert(b"c", b"3");
+        let records = batch.to_records(|key, value| Record::new(0, key, value.unwrap())).unwrap();
+        let (commit, members) = records.split_last().unwrap();
+        store.append(members).unwrap();
+        assert!(changes(&mut feed).is_empty());
+        assert_eq!(feed.position(), resume);
+        store.append(std::slice::from_ref(commit)).unwrap();
+        let expected = vec![
+            (b"b".to_vec(), Some(b"2".to_vec())),
+            (b"c".to_vec(), Some(b"3".to_vec())),
+        ];
+        assert_eq!(changes(&mut feed), expected);
+
+        // 从保存的位置恢复
+        let mut resumed = store.subscribe(resume).unwrap();
+        assert_eq!(changes(&mut resumed), expected);
+
+        store.compact().unwrap();
+        store.insert(b"d", b"4").unwrap();
+        assert!(feed.next().unwrap().is_err());
+        fs::remove_file(store.log.sidecar_path("hint")).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn subscription_crosses_segments() {
+        let _ = fs::remove_dir_all(temp_path("subscribe-segments"));
+        let options = Options { segment_size: Some(64), ..Options::default() };
+        let (path, mut store) = open("subscribe-segments", options);
+        let mut feed = store.subscribe(0).unwrap();
+        for i in 0..10 {
+            store.insert(format!("k{}", i).as_bytes(), b"value").unwrap();
+        }
+        assert!(segment_of(store.log.end().unwrap()) > 0);
+        let keys: Vec<_> = changes(&mut feed).into_iter().map(|(key, _)| key).collect();
+        assert_eq!(keys.len(), 10);
+        assert_eq!(keys[9], b"k9");
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn namespaces_are_separate_keyspaces() {
+        let (path, dest, restored) = (
+            temp_path("namespaces"),
+            temp_path("namespaces-backup"),
+            temp_path("namespaces-restored"),
+        );
+        let clock = Arc::new(ManualClock::default());
+        let options = Options { clock: clock.clone(), ..Options::default() };
+        clock.set(1_000);
+        let mut store = reopen(&path, options.clone());
+        store.insert(b"k", b"default").unwrap();
+        store.create_namespace("users").unwrap().insert(b"k", b"user").unwrap();
+        let mut orders = store.create_namespace("orders").unwrap();
+        orders.insert(b"k", b"order").unwrap();
+        orders.insert_with_ttl(b"cart", b"c", Duration::from_millis(100)).unwrap();
+        orders.delete(b"k").unwrap();
+        store.create_namespace("users").unwrap().insert(b"u2", b"2").unwrap();
+
+        let check = |store: &ActionKV| {
+            assert_eq!(store.namespaces().collect::<Vec<_>>(), ["orders", "users"]);
+            assert_eq!(store.get(b"k").unwrap(), Some(b"default".to_vec()));
+            assert_eq!(store.keys().count(), 1);
+            let users = store.namespace("users").unwrap();
+            assert_eq!(users.get(b"k").unwrap(), Some(b"user".to_vec()));
+            assert_eq!(users.prefix(b"u").count(), 1);
+            let orders = store.namespace("orders").unwrap();
+            assert_eq!(orders.get(b"k").unwrap(), None);
+            assert_eq!(orders.keys().collect::<Vec<_>>(), [b"cart"]);
+        };
+        check(&store);
+        let end = store.log.end().unwrap();
+        // 查找不存在的命名空间不会创建它，也不写入日志；快照中同样可以查找
+        assert!(store.namespace("missing").is_none());
+        assert_eq!(store.log.end().unwrap(), end);
+        let snapshot = store.snapshot().unwrap();
+        assert!(snapshot.namespace("missing").is_none());
+        let users = snapshot.namespace("users").unwrap();
+        assert_eq!(users.get(b"k").unwrap(), Some(b"user".to_vec()));
+        assert_eq!(users.scan::<std::ops::RangeFull>(..).count(), 2);
+        assert_eq!(store.get_as_of(b"k", end).unwrap(), Some(b"default".to_vec()));
+        let feed = store.subscribe(0).unwrap();
+        let namespaces: Vec<u32> = feed.map(|change| change.unwrap().namespace).collect();
+        assert_eq!(namespaces, [0, 1, 2, 2, 2, 1]);
+
+        // 用 hint 加载、完整扫描、备份恢复，结果都一样
+        store.close().unwrap();
+        let store = reopen(&path, options.clone());
+        check(&store);
+        hint::remove(store.log.storage().as_ref()).unwrap();
+        let store = reopen(&path, options.clone());
+        check(&store);
+        store.backup(&dest).unwrap();
+        let restored_store = ActionKV::restore(&dest, &restored, options).unwrap();
+        check(&restored_store);
+
+        clock.set(1_100);
+        assert_eq!(store.namespace("orders").unwrap().keys().count(), 0);
+        fs::remove_file(restored_store.log.sidecar_path("hint")).unwrap();
+        for path in [&path, &dest, &restored] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn namespace_compaction_and_export() {
+        let (path, copy) = (temp_path("namespace-compact"), temp_path("namespace-copy"));
+        let mut store = reopen(&path, Options::default());
+        for i in 0..3 {
+            store.insert(b"a", format!("{}", i).as_bytes()).unwrap();
+            let value = format!("{}", i);
+            store.create_namespace("users").unwrap().insert(b"a", value.as_bytes()).unwrap();
+        }
+        store.insert(b"gone", b"x").unwrap();
+        store.delete(b"gone").unwrap();
+        store.create_namespace("users").unwrap().delete(b"a").unwrap();
+        store.create_namespace("users").unwrap().insert(b"b", b"1").unwrap();
+
+        // 只回收 users 的旧记录：默认命名空间的 4 条旧记录（2 次覆盖、gone 和它的墓碑）还在，
+        // 另外一条是压缩后写入的版本号标记
+        store.create_namespace("users").unwrap().compact().unwrap();
+        let report = store.fsck().unwrap();
+        assert_eq!(report.dead_records, 4 + 1);
+        // 目录中 users 的条目单独统计，不算作 key
+        assert_eq!((report.live_keys, report.namespaces), (2, 1));
+        assert_eq!(store.get(b"a").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(store.get(b"gone").unwrap(), None);
+        assert_eq!(store.namespace("users").unwrap().get(b"a").unwrap(), None);
+        store.load().unwrap();
+        assert_eq!(store.get(b"gone").unwrap(), None);
+        assert_eq!(store.namespace("users").unwrap().get(b"b").unwrap(), Some(b"1".to_vec()));
+
+        // 整个存储的压缩保留所有命名空间，只剩下每个 key 最新的记录和一个版本号标记
+        store.compact().unwrap();
+        assert_eq!(store.fsck().unwrap().dead_records, 1);
+        assert_eq!(store.namespace("users").unwrap().get(b"b").unwrap(), Some(b"1".to_vec()));
+
+        let mut out = Vec::new();
+        assert_eq!(store.namespace("users").unwrap().export(&mut out, DumpFormat::Csv).unwrap(), 1);
+        let mut other = reopen(&copy, Options::default());
+        other.create_namespace("people").unwrap().import(out.as_slice(), DumpFormat::Csv).unwrap();
+        assert_eq!(other.namespace("people").unwrap().get(b"b").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(other.get(b"b").unwrap(), None);
+
+        fs::remove_file(store.log.sidecar_path("hint")).unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&copy).unwrap();
+    }
+
+    #[test]
+    fn namespace_compaction_after_skip_recovery() {
+        let (path, mut store) = open("namespace-skip", Options::default());
+        store.insert(b"a", b"1").unwrap();
+        store.insert(b"b", b"2").unwrap();
+        for value in [b"1", b"2"] {
+            store.create_namespace("users").unwrap().insert(b"x", value).unwrap();
+        }
+        let damaged = store.index[&b"a".to_vec()];
+        let len = store.log.record_len_at(damaged).unwrap();
+        drop(store);
+
+        // a 的 value 被改坏，只能用 Skip 加载
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[(damaged + len) as usize - 1] ^= 1;
+        fs::write(&path, &bytes).unwrap();
+        let mut store = ActionKV::open(&path).unwrap();
+        assert!(store.load_with(Recovery::Strict).is_err());
+        assert_eq!(store.load_with(Recovery::Skip).unwrap().corrupt.len(), 1);
+
+        // 压缩 users 时同样跳过损坏的记录，新文件可以严格加载
+        store.create_namespace("users").unwrap().compact().unwrap();
+        hint::remove(store.log.storage().as_ref()).unwrap();
+        let store = reopen(&path, Options::default());
+        assert_eq!(store.keys().collect::<Vec<_>>(), [b"b"]);
+        assert_eq!(store.namespace("users").unwrap().get(b"x").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(store.fsck().unwrap().dead_records, 1);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn memory_storage_reloads_compacts_and_subscribes() {
+        let options = Options { segment_size: Some(64), ..Options::default() };
+        let storage = Arc::new(MemoryStorage::new());
+        let mut store = ActionKV::open_with_storage(storage.clone(), options.clone()).unwrap();
+        store.load().unwrap();
+        for i in 0..10 {
+            store.insert(format!("k{}", i).as_bytes(), b"value").unwrap();
+        }
+        store.delete(b"k0").unwrap();
+        store.create_namespace("users").unwrap().insert(b"a", b"1").unwrap();
+        assert!(store.log.segments().len() > 1);
+        store.close().unwrap();
+
+        // 同一个后端重新打开，从 hint 恢复
+        let mut store = ActionKV::open_with_storage(storage.clone(), options.clone()).unwrap();
+        assert!(store.load_with(Recovery::Strict).unwrap().hinted);
+        assert_eq!(store.keys().count(), 9);
+        assert_eq!(store.namespace("users").unwrap().get(b"a").unwrap(), Some(b"1".to_vec()));
+
+        let mut feed = store.subscribe(0).unwrap();
+        assert_eq!(feed.by_ref().count(), 12);
+        store.compact().unwrap();
+        assert!(feed.next().unwrap().is_err());
+        let changes: Vec<Change> = store.subscribe(0).unwrap().map(Result::unwrap).collect();
+        assert_eq!(changes.len(), 10);
+
+        let mut store = ActionKV::open_with_storage(storage, options).unwrap();
+        store.load().unwrap();
+        assert_eq!(store.get(b"k0").unwrap(), None);
+        assert_eq!(store.get(b"k9").unwrap(), Some(b"value".to_vec()));
+        assert_eq!(store.fsck().unwrap().dead_records, 1);
+    }
+
+    #[test]
+    fn short_write_is_rolled_back() {
+        let (mut store, faults) = faulty(Options::default());
+        store.insert(b"a", b"1").unwrap();
+        faults.short_write(5);
+        assert!(store.insert(b"b", b"2").is_err());
+        assert_eq!(store.get(b"b").unwrap(), None);
+        store.insert(b"c", b"3").unwrap();
+
+        let mut store = ActionKV::open_with_storage(faults, Options::default()).unwrap();
+        let report = store.load_with(Recovery::Strict).unwrap();
+        assert_eq!(report.records, 2);
+        assert_eq!(store.get(b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(store.get(b"b").unwrap(), None);
+        assert_eq!(store.get(b"c").unwrap(), Some(b"3".to_vec()));
+    }
+
+    #[test]
+    fn flipped_bit_is_reported_as_corruption() {
+        let (mut store, faults) = faulty(Options::default());
+        store.insert(b"a", b"1").unwrap();
+        store.insert(b"b", b"2").unwrap();
+        let position = store.index[&b"a".to_vec()];
+        // 翻转 value 中的一位，头部完好，可以跳过这条记录
+        let len = store.log.record_len_at(position).unwrap();
+        faults.flip_bit(position + len - 1, 3);
+
+        let err = store.get(b"a").unwrap_err();
+        assert_eq!(Corruption::from_io_error(&err).unwrap().offset, position);
+        assert_eq!(store.get(b"b").unwrap(), Some(b"2".to_vec()));
+        let report = store.load_with(Recovery::Skip).unwrap();
+        assert_eq!(report.corrupt.len(), 1);
+        assert_eq!(report.corrupt[0].offset, position);
+        assert!(!store.contains_key(b"a"));
     }
 }
@@ -0,0 +1,628 @@
+// 日志：由一个或多个段组成的追加写日志，段存放在 Storage 中，见 storage.rs
+// 单文件存储只有 0 号段；分段存储的段号从 0 开始递增
+
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
+use std::ops::Range;
+#[cfg(test)]
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::batch::Pending;
+use crate::record::{self, Record};
+use crate::storage::{Segment, Storage};
+use crate::{
+    hint, make_position, offset_of, segment_of, ByteStr, Corruption, Durability, LoadReport,
+    Options, Recovery, CRC, MAX_SEGMENT_ID, OFFSET_MASK,
+};
+
+// 段号到段
+pub(crate) type Segments = BTreeMap<u32, Arc<dyn Segment>>;
+
+#[derive(Debug)]
+pub(crate) struct Log {
+    storage: Arc<dyn Storage>,
+    segment_size: Option<u64>,
+    durability: Durability,
+    // 段号 -> 段，最后一个是唯一可写的活动段
+    segments: Segments,
+    // 上次 fsync 之后写入的字节数和时间，用于组提交
+    unsynced: u64,
+    last_sync: Instant,
+    // 写入失败后没能截断写了一半的数据，日志末尾的状态未知，之后的写入都会失败
+    poisoned: bool,
+    // 组提交时在后台按 interval 落盘，见 Flusher
+    flusher: Option<Flusher>,
+}
+
+impl Log {
+    pub fn open(storage: Arc<dyn Storage>, options: &Options) -> io::Result<Log> {
+        let flusher = match options.durability {
+            Durability::GroupCommit { interval, .. } => Some(Flusher::spawn(interval)?),
+            _ => None,
+        };
+        let mut log = Log {
+            storage,
+            segment_size: options.segment_size,
+            durability: options.durability,
+            segments: BTreeMap::new(),
+            unsynced: 0,
+            last_sync: Instant::now(),
+            poisoned: false,
+            flusher,
+        };
+        log.open_segments()?;
+        Ok(log)
+    }
+
+    // 打开所有段，没有段时创建 0 号段作为活动段
+    fn open_segments(&mut self) -> io::Result<()> {
+        let mut ids = self.storage.segment_ids()?;
+        if ids.is_empty() {
+            ids.push(0);
+        }
+        self.segments.clear();
+        for id in ids {
+            self.segments.insert(id, self.storage.open(id)?);
+        }
+        Ok(())
+    }
+
+    pub fn storage(&self) -> &Arc<dyn Storage> {
+        &self.storage
+    }
+
+    // 测试直接读写 hint 文件
+    #[cfg(test)]
+    pub fn sidecar_path(&self, name: &str) -> PathBuf {
+        self.storage.sidecar_path(name).expect("storage is not file backed")
+    }
+
+    fn active_id(&self) -> u32 {
+        *self.segments.keys().next_back().expect("store has no active segment")
+    }
+
+    fn segment(&self, id: u32) -> io::Result<&Arc<dyn Segment>> {
+        segment(&self.segments, id)
+    }
+
+    pub fn segments(&self) -> &Segments {
+        &self.segments
+    }
+
+    // 复制所有段的引用，之后的压缩替换掉旧段时，复制的引用仍然指向旧段
+    pub fn clone_segments(&self) -> Segments {
+        self.segments.clone()
+    }
+
+    // 活动段写满后变为只读，并打开一个新段
+    // 封存前先落盘，之后的 sync 只需要处理新的活动段
+    fn roll_segment(&mut self) -> io::Result<()> {
+        let id = self.active_id();
+        if id >= MAX_SEGMENT_ID {
+            return Err(io::Error::other("too many segments"));
+        }
+        self.sync()?;
+        self.segments.insert(id + 1, self.storage.open(id + 1)?);
+        Ok(())
+    }
+
+    // 日志开头的位置，分段存储压缩之后第一个段不再是 0 号段
+    pub fn start(&self) -> u64 {
+        let id = *self.segments.keys().next().expect("store has no active segment");
+        make_position(id, 0)
+    }
+
+    // 日志末尾的位置
+    pub fn end(&self) -> io::Result<u64> {
+        let id = self.active_id();
+        let offset = self.segment(id)?.len()?;
+        Ok(make_position(id, offset))
+    }
+
+    // 所有段的总字节数
+    pub fn size(&self) -> io::Result<u64> {
+        self.segments.values().map(|segment| segment.len()).sum()
+    }
+
+    // position 之前（同一段内）最多 TAIL_LEN 字节的校验和，position 不在日志之内时返回 None
+    // 用来判断 hint 之后日志有没有被截断或替换
+    pub fn tail_checksum(&self, position: u64) -> io::Result<Option<u32>> {
+        const TAIL_LEN: u64 = 64;
+        let segment = match self.segments.get(&segment_of(position)) {
+            Some(segment) => segment,
+            None => return Ok(None),
+        };
+        let offset = offset_of(position);
+        if segment.len()? < offset {
+            return Ok(None);
+        }
+        let start = offset.saturating_sub(TAIL_LEN);
+        let mut tail = vec![0; (offset - start) as usize];
+        PositionalReader::new(segment.as_ref(), start).read_exact(&mut tail)?;
+        Ok(Some(CRC.checksum(&tail)))
+    }
+
+    // 一次写入整段字节，返回写入的位置
+    // 同一次写入的内容总在同一个段里，写完之后才判断是否需要换段
+    // 按 durability 决定是否 fsync
+    // 写入、fsync 或换段失败时返回错误，并把这次写入的数据截断掉，
+    // 否则下次打开时它会被当成成功的写入；截断也失败时日志不再接受写入，需要重新打开
+    pub fn append(&mut self, bytes: &ByteStr) -> io::Result<u64> {
+        if self.poisoned {
+            return Err(io::Error::other("an earlier write failed, reopen the store"));
+        }
+        let id = self.active_id();
+        let segment = self.segment(id)?;
+        let offset = segment.len()?;
+        if offset + bytes.len() as u64 > OFFSET_MASK {
+            return Err(io::Error::other(format!("segment {} is full", id)));
+        }
+        if let Err(err) = write_all(segment.as_ref(), bytes) {
+            self.undo(id, offset);
+            return Err(err);
+        }
+        self.unsynced += bytes.len() as u64;
+
+        if let Err(err) = self.finish_append(offset + bytes.len() as u64) {
+            self.undo(id, offset);
+            self.unsynced = self.unsynced.saturating_sub(bytes.len() as u64);
+            return Err(err);
+        }
+        Ok(make_position(id, offset))
+    }
+
+    // 追加之后按 durability 落盘，活动段写到 end 之后判断是否需要换段
+    fn finish_append(&mut self, end: u64) -> io::Result<()> {
+        let due = match self.durability {
+            Durability::Always => true,
+            Durability::GroupCommit { interval, bytes } => {
+                self.unsynced >= bytes || self.last_sync.elapsed() >= interval
+            }
+            Durability::Buffered => false,
+        };
+        if due {
+            self.sync()?;
+        } else if let Some(flusher) = &self.flusher {
+            flusher.mark(self.segment(self.active_id())?);
+        }
+
+        if let Some(segment_size) = self.segment_size {
+            if end >= segment_size {
+                self.roll_segment()?;
+            }
+        }
+        Ok(())
+    }
+
+    // 把 id 号段截断回 offset，去掉失败的写入
+    fn undo(&mut self, id: u32, offset: u64) {
+        let truncated = self.segment(id).and_then(|segment| segment.truncate(offset));
+        self.poisoned = truncated.is_err();
+    }
+
+    pub fn read_at(&self, position: u64) -> io::Result<Record> {
+        read_at(&self.segments, position)
+    }
+
+    pub fn record_len_at(&self, position: u64) -> io::Result<u64> {
+        let segment = self.segment(segment_of(position))?;
+        record::len_at(&mut PositionalReader::new(segment.as_ref(), offset_of(position)))
+    }
+
+    // 段的写入不经过缓冲，追加后已经交给后端
+    pub fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    // 把活动段的数据落盘，已封存的段在换段时已经落盘
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.segment(self.active_id())?.sync()?;
+        self.synced();
+        Ok(())
+    }
+
+    fn synced(&mut self) {
+        self.unsynced = 0;
+        self.last_sync = Instant::now();
+        if let Some(flusher) = &self.flusher {
+            flusher.clear();
+        }
+    }
+
+    // 从 from 开始按顺序扫描日志，只把已提交的记录交给 apply
+    // 返回日志是否停在一个未提交的批次中
+    pub fn scan<F>(
+        &self,
+        from: u64,
+        recovery: Recovery,
+        report: &mut LoadReport,
+        apply: F,
+    ) -> io::Result<bool>
+    where
+        F: FnMut(u64, Record),
+    {
+        self.scan_until(from, u64::MAX, recovery, report, apply)
+    }
+
+    // 只扫描 [from, to) 之间的日志，越过 to 的记录和到 to 时仍未提交的批次都不生效
+    // to 不是日志末尾时不要用 Recovery::Truncate，越过 to 的记录会被当成写了一半的尾部
+    pub fn scan_until<F>(
+        &self,
+        from: u64,
+        to: u64,
+        recovery: Recovery,
+        report: &mut LoadReport,
+        mut apply: F,
+    ) -> io::Result<bool>
+    where
+        F: FnMut(u64, Record),
+    {
+        let mut pending = Pending::default();
+        let ids: Vec<u32> = self
+            .segments
+            .range(segment_of(from)..=segment_of(to.max(from)))
+            .map(|(id, _)| *id)
+            .collect();
+        for id in ids {
+            let start = if id == segment_of(from) { offset_of(from) } else { 0 };
+            let limit = if id == segment_of(to) { offset_of(to) } else { u64::MAX };
+            self.scan_segment(id, start..limit, recovery, report, &mut pending, &mut apply)?;
+        }
+
+        let open = pending.is_open();
+        pending.finish();
+        report.incomplete_batches += pending.discarded;
+        Ok(open)
+    }
+
+    fn scan_segment<F>(
+        &self,
+        id: u32,
+        // 段内要扫描的偏移范围，end 可以超出段的长度
+        range: Range<u64>,
+        recovery: Recovery,
+        report: &mut LoadReport,
+        pending: &mut Pending,
+        apply: &mut F,
+    ) -> io::Result<()>
+    where
+        F: FnMut(u64, Record),
+    {
+        let segment = self.segment(id)?.as_ref();
+        let end = segment.len()?.min(range.end);
+        // 读取不能越过 end，跨过 end 的记录按不完整处理
+        let reader = |offset: u64| {
+            BufReader::new(PositionalReader::new(segment, offset).take(end.saturating_sub(offset)))
+        };
+        let mut offset = range.start;
+        let mut f = reader(offset);
+        let mut torn_tail = None;
+
+        while offset < end {
+            let position = make_position(id, offset);
+            let record = match Record::read(&mut f, position) {
+                Ok(record) => record,
+                Err(err) if recovery == Recovery::Strict => return Err(err),
+                Err(err) => match classify(segment, id, offset, end, err)? {
+                    None => {
+                        torn_tail = Some(offset);
+                        break;
+                    }
+                    // 跳过损坏的部分，从下一条完整的记录继续
+                    Some(corruption) => {
+                        offset += corruption.len;
+                        f = reader(offset);
+                        report.corrupt.push(corruption);
+                        continue;
+                    }
+                },
+            };
+            let (len, checksum, version) = (record.len(), record.checksum, record.version);
+            offset += len;
+            let ready = match pending.push(position, record) {
+                Ok(ready) => ready,
+                Err(err) if recovery == Recovery::Strict => return Err(err),
+                // 校验通过但无效的批次标记，和其他无效的记录一样跳过
+                Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                    report.corrupt.push(invalid(position, len, checksum));
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            report.records += 1;
+            report.max_version = report.max_version.max(version.unwrap_or(0));
+
+            for (position, record) in ready {
+                apply(position, record);
+            }
+        }
+
+        if let Some(tail) = torn_tail {
+            report.torn_tail = Some(make_position(id, tail));
+            if recovery == Recovery::Truncate {
+                segment.truncate(tail)?;
+                report.truncated_to = Some(make_position(id, tail));
+            }
+        }
+
+        Ok(())
+    }
+
+    // 把 positions 处的记录按顺序写入新的暂存段，再一次替换旧段，返回旧位置到新位置的映射
+    // 分段存储的新段号接在旧段之后，中途崩溃时旧段仍然完整，重复的记录以新段为准
+    pub fn compact(&mut self, mut positions: Vec<u64>) -> io::Result<HashMap<u64, u64>> {
+        // 按日志中的顺序读取，顺序读比随机读快
+        positions.sort_unstable();
+
+        let first_id = match self.segment_size {
+            None => 0,
+            Some(_) => self.active_id() + 1,
+        };
+        let mut id = first_id;
+        let mut offset = 0;
+        let mut f = BufWriter::new(SegmentWriter(self.storage.stage(id)?));
+        let mut moved = HashMap::with_capacity(positions.len());
+        for position in positions {
+            if let Some(segment_size) = self.segment_size {
+                if offset >= segment_size {
+                    finish_segment(f)?;
+                    id += 1;
+                    offset = 0;
+                    f = BufWriter::new(SegmentWriter(self.storage.stage(id)?));
+                }
+            }
+            let record = self.read_at(position)?;
+            let written = record.write(&mut f)?;
+            moved.insert(position, make_position(id, offset));
+            offset += written;
+        }
+        finish_segment(f)?;
+
+        // 旧的 hint 指向旧段的偏移，必须在替换段之前删除
+        hint::remove(self.storage.as_ref())?;
+        let old_ids: Vec<u32> = match self.segment_size {
+            None => Vec::new(),
+            Some(_) => self.segments.keys().copied().collect(),
+        };
+        let new_ids: Vec<u32> = (first_id..=id).collect();
+        self.segments.clear();
+        self.storage.commit(&new_ids, &old_ids)?;
+
+        self.open_segments()?;
+        // 新段在 commit 之前都已落盘
+        self.synced();
+        Ok(moved)
+    }
+}
+
+// 组提交时把最后一批没有落盘的写入落盘，不必等后台线程
+// 这里的错误无法返回，需要确认落盘时应先调用 sync
+impl Drop for Log {
+    fn drop(&mut self) {
+        if let Durability::GroupCommit { .. } = self.durability {
+            if self.unsynced > 0 && !self.poisoned {
+                let _ = self.sync();
+            }
+        }
+    }
+}
+
+// 组提交的后台线程：最早一次没有落盘的写入过了 interval 还没有落盘时，把它所在的段落盘
+// 这样存储空闲时最后一批写入也最多等 interval；写入时达到条件仍然由写入者自己落盘
+#[derive(Debug)]
+struct Flusher {
+    shared: Arc<(Mutex<Unsynced>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+#[derive(Debug, Default)]
+struct Unsynced {
+    // 最早一次没有落盘的写入所在的段和写入的时间
+    since: Option<(Arc<dyn Segment>, Instant)>,
+    stop: bool,
+}
+
+impl Flusher {
+    fn spawn(interval: Duration) -> io::Result<Flusher> {
+        let shared = Arc::new((Mutex::new(Unsynced::default()), Condvar::new()));
+        let state = shared.clone();
+        let thread = thread::Builder::new()
+            .name("actionkv-flusher".to_string())
+            .spawn(move || flush_every(&state, interval))?;
+        Ok(Flusher { shared, thread: Some(thread) })
+    }
+
+    // 记下 segment 有没有落盘的写入，已经有更早的写入在等待时不变
+    fn mark(&self, segment: &Arc<dyn Segment>) {
+        let (lock, wake) = &*self.shared;
+        let mut unsynced = lock.lock().unwrap();
+        if unsynced.since.is_none() {
+            unsynced.since = Some((segment.clone(), Instant::now()));
+            wake.notify_one();
+        }
+    }
+
+    fn clear(&self) {
+        self.shared.0.lock().unwrap().since = None;
+    }
+}
+
+impl Drop for Flusher {
+    fn drop(&mut self) {
+        let (lock, wake) = &*self.shared;
+        lock.lock().unwrap().stop = true;
+        wake.notify_one();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn flush_every(shared: &(Mutex<Unsynced>, Condvar), interval: Duration) {
+    let (lock, wake) = shared;
+    let mut unsynced = lock.lock().unwrap();
+    while !unsynced.stop {
+        let due = match &unsynced.since {
+            Some((_, since)) => *since + interval,
+            None => {
+                unsynced = wake.wait(unsynced).unwrap();
+                continue;
+            }
+        };
+        let now = Instant::now();
+        if now < due {
+            unsynced = wake.wait_timeout(unsynced, due - now).unwrap().0;
+            continue;
+        }
+        // 落盘时不持有锁，期间的写入重新记下时间，等下一次落盘
+        let (segment, _) = unsynced.since.take().unwrap();
+        drop(unsynced);
+        let result = segment.sync();
+        unsynced = lock.lock().unwrap();
+        // 失败时过一个 interval 再试，写入者下一次落盘时会返回这个错误
+        if result.is_err() && unsynced.since.is_none() {
+            unsynced.since = Some((segment, Instant::now()));
+        }
+    }
+}
+
+fn segment(segments: &Segments, id: u32) -> io::Result<&Arc<dyn Segment>> {
+    segments.get(&id).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, format!("segment {} not found", id))
+    })
+}
+
+// 读取 position 处的记录，快照和订阅用自己复制的段读取
+pub(crate) fn read_at(segments: &Segments, position: u64) -> io::Result<Record> {
+    let segment = segment(segments, segment_of(position))?;
+    let mut f = BufReader::new(PositionalReader::new(segment.as_ref(), offset_of(position)));
+    Record::read(&mut f, position)
+}
+
+// 判断 offset 处读取失败的记录是写了一半的尾部（返回 None），还是需要跳过的损坏
+// 只有剩下的字节不够一个头部，或者最后一条记录校验失败时才是尾部；
+// 记录越过段的末尾时可能是长度字段损坏，向后找到下一条完整的记录时只跳过中间的部分，
+// 找不到时它就是最后一条记录，按写了一半处理
+fn classify(
+    segment: &dyn Segment,
+    id: u32,
+    offset: u64,
+    end: u64,
+    err: io::Error,
+) -> io::Result<Option<Corruption>> {
+    if end - offset < record::HEADER_LEN {
+        return Ok(None);
+    }
+    match err.kind() {
+        io::ErrorKind::InvalidData => {
+            let corruption = match Corruption::from_io_error(&err) {
+                Some(corruption) => corruption.clone(),
+                // 校验通过但内容无效（标志组合不对、附加字段不完整），记录是完整的，不是残缺的尾部
+                None => {
+                    let mut header = [0; record::HEADER_LEN as usize];
+                    PositionalReader::new(segment, offset).read_exact(&mut header)?;
+                    let len = record::len_at(&mut &header[..])?;
+                    let checksum = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+                    return Ok(Some(invalid(make_position(id, offset), len, checksum)));
+                }
+            };
+            Ok((offset + corruption.len < end).then_some(corruption))
+        }
+        io::ErrorKind::UnexpectedEof => {
+            let mut checksum = [0; 4];
+            PositionalReader::new(segment, offset).read_exact(&mut checksum)?;
+            let next = match resync(segment, offset + 1, end)? {
+                Some(next) => next,
+                None => return Ok(None),
+            };
+            Ok(Some(Corruption {
+                offset: make_position(id, offset),
+                len: next - offset,
+                expected: u32::from_le_bytes(checksum),
+                actual: 0,
+            }))
+        }
+        _ => Err(err),
+    }
+}
+
+// 校验通过但无法使用的记录，expected 和 actual 都是它的校验和
+pub(crate) fn invalid(position: u64, len: u64, checksum: u32) -> Corruption {
+    Corruption { offset: position, len, expected: checksum, actual: checksum }
+}
+
+// 从 from 开始逐个字节寻找下一条完整并且校验通过的记录，返回它的偏移
+fn resync(segment: &dyn Segment, from: u64, end: u64) -> io::Result<Option<u64>> {
+    const EMPTY: [u8; record::HEADER_LEN as usize] = [0; record::HEADER_LEN as usize];
+    let mut header = EMPTY;
+    for offset in from..end.saturating_sub(record::HEADER_LEN - 1) {
+        PositionalReader::new(segment, offset).read_exact(&mut header)?;
+        let len = record::len_at(&mut &header[..])?;
+        // 全为 0 的头部是一条能通过校验的空记录，在损坏的数据中不能当作记录
+        if header == EMPTY || offset + len > end {
+            continue;
+        }
+        let mut f = BufReader::new(PositionalReader::new(segment, offset).take(len));
+        if Record::read(&mut f, 0).is_ok() {
+            return Ok(Some(offset));
+        }
+    }
+    Ok(None)
+}
+
+// 后端可能一次只写入一部分
+fn write_all(segment: &dyn Segment, mut bytes: &ByteStr) -> io::Result<()> {
+    while !bytes.is_empty() {
+        match segment.append(bytes) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => bytes = &bytes[n..],
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+// 压缩时向暂存段写入
+struct SegmentWriter(Arc<dyn Segment>);
+
+impl Write for SegmentWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.append(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// commit 之前必须落盘，否则断电后可能得到一个空的段
+fn finish_segment(f: BufWriter<SegmentWriter>) -> io::Result<()> {
+    let f = f.into_inner().map_err(|err| err.into_error())?;
+    f.0.sync()
+}
+
+// 按偏移读取，不依赖读写位置，多个线程可以同时读同一个段
+struct PositionalReader<'a> {
+    segment: &'a dyn Segment,
+    offset: u64,
+}
+
+impl<'a> PositionalReader<'a> {
+    fn new(segment: &'a dyn Segment, offset: u64) -> Self {
+        PositionalReader { segment, offset }
+    }
+}
+
+impl Read for PositionalReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.segment.read_at(buf, self.offset)?;
+        self.offset += n as u64;
+        Ok(n)
+    }
+}
@@ -0,0 +1,132 @@
+// 内存后端：段和辅助数据都放在内存里，进程退出后全部丢失
+//
+// 同一个 MemoryStorage 可以先后交给多个 ActionKV 打开，相当于重新打开同一个存储，
+// 测试加载、压缩、崩溃恢复时不必创建文件
+// sync 什么也不做，写入后立即可见
+
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+
+use crate::storage::{Segment, Storage};
+use crate::{ByteStr, ByteString};
+
+// 段的身份，每个新段一个
+static IDENTITIES: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    segments: BTreeMap<u32, Arc<MemorySegment>>,
+    staged: BTreeMap<u32, Arc<MemorySegment>>,
+    sidecars: HashMap<String, ByteString>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        MemoryStorage::default()
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().expect("memory storage poisoned")
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn segment_ids(&self) -> io::Result<Vec<u32>> {
+        Ok(self.state().segments.keys().copied().collect())
+    }
+
+    fn open(&self, id: u32) -> io::Result<Arc<dyn Segment>> {
+        let segment = self.state().segments.entry(id).or_insert_with(MemorySegment::new).clone();
+        Ok(segment)
+    }
+
+    fn open_existing(&self, id: u32) -> io::Result<Option<Arc<dyn Segment>>> {
+        Ok(self.state().segments.get(&id).map(|segment| segment.clone() as _))
+    }
+
+    fn stage(&self, id: u32) -> io::Result<Arc<dyn Segment>> {
+        let segment = MemorySegment::new();
+        self.state().staged.insert(id, segment.clone());
+        Ok(segment)
+    }
+
+    fn commit(&self, staged: &[u32], removed: &[u32]) -> io::Result<()> {
+        let mut state = self.state();
+        for id in staged {
+            let segment = state.staged.remove(id).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, format!("segment {} was not staged", id))
+            })?;
+            state.segments.insert(*id, segment);
+        }
+        for id in removed {
+            state.segments.remove(id);
+        }
+        Ok(())
+    }
+
+    fn read_sidecar(&self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.state().sidecars.get(name).cloned())
+    }
+
+    fn write_sidecar(&self, name: &str, data: &ByteStr) -> io::Result<()> {
+        self.state().sidecars.insert(name.to_string(), data.to_vec());
+        Ok(())
+    }
+
+    fn remove_sidecar(&self, name: &str) -> io::Result<()> {
+        self.state().sidecars.remove(name);
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct MemorySegment {
+    data: RwLock<ByteString>,
+    identity: u64,
+}
+
+impl MemorySegment {
+    fn new() -> Arc<Self> {
+        let identity = IDENTITIES.fetch_add(1, Ordering::Relaxed);
+        Arc::new(MemorySegment { data: RwLock::default(), identity })
+    }
+}
+
+impl Segment for MemorySegment {
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.data.read().expect("memory segment poisoned").len() as u64)
+    }
+
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let data = self.data.read().expect("memory segment poisoned");
+        let start = (offset as usize).min(data.len());
+        let n = buf.len().min(data.len() - start);
+        buf[..n].copy_from_slice(&data[start..start + n]);
+        Ok(n)
+    }
+
+    fn append(&self, buf: &ByteStr) -> io::Result<usize> {
+        self.data.write().expect("memory segment poisoned").extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn truncate(&self, len: u64) -> io::Result<()> {
+        self.data.write().expect("memory segment poisoned").truncate(len as usize);
+        Ok(())
+    }
+
+    fn identity(&self) -> io::Result<(u64, u64)> {
+        Ok((0, self.identity))
+    }
+}
@@ -0,0 +1,226 @@
+// 命名空间：同一个存储里互相独立的 key 空间，共用一个日志、一组文件句柄和一份备份
+//
+// 每条记录带有命名空间 id（见 record.rs 的 FLAG_NAMESPACE），没有这个标志的记录属于默认命名空间，
+// 默认命名空间就是 ActionKV 自己的 index，ActionKV 上原有的 API 都只作用于它
+// 其他命名空间各有一份 index 和 expires：ActionKV::namespace 和 Snapshot::namespace 查找已有的
+// 命名空间，返回只读的 Namespace，查找不会写入；ActionKV::create_namespace 在需要时创建它，
+// 返回可以写入的 NamespaceMut
+//
+// 名字到 id 的映射也写在日志里：目录（id CATALOG）中 key 是名字，value 是 id u32，
+// 和普通记录一样参与 load、hint、压缩、备份和复制，但不压缩也不加密
+// 命名空间创建后不会删除，id 不会重复使用
+
+use std::collections::{btree_map, BTreeMap};
+use std::io;
+use std::ops::{RangeBounds, RangeFull};
+use std::time::Duration;
+
+use crate::log::{self, Segments};
+use crate::scan::{self, Scan};
+use crate::{dump, EncryptionKey};
+use crate::{ActionKV, ByteStr, ByteString, DumpFormat, Expires, Index};
+
+pub(crate) const DEFAULT: u32 = 0;
+pub(crate) const CATALOG: u32 = u32::MAX;
+
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Keyspace {
+    pub index: Index,
+    pub expires: Expires,
+}
+
+// 默认命名空间以外的命名空间，包括目录
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Namespaces {
+    names: BTreeMap<String, u32>,
+    spaces: BTreeMap<u32, Keyspace>,
+    // 还没有记录的命名空间借用这个空的 keyspace
+    empty: Keyspace,
+}
+
+impl Namespaces {
+    pub fn clear(&mut self) {
+        self.names.clear();
+        self.spaces.clear();
+    }
+
+    pub fn id(&self, name: &str) -> Option<u32> {
+        self.names.get(name).copied()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.names.keys().map(String::as_str)
+    }
+
+    pub fn insert_name(&mut self, name: String, id: u32) {
+        self.names.insert(name, id);
+    }
+
+    // 下一个可用的 id，从 1 开始
+    pub fn next_id(&self) -> io::Result<u32> {
+        let next = self.names.values().max().map_or(1, |id| id + 1);
+        if next == CATALOG {
+            return Err(io::Error::other("too many namespaces"));
+        }
+        Ok(next)
+    }
+
+    pub fn get(&self, id: u32) -> &Keyspace {
+        self.spaces.get(&id).unwrap_or(&self.empty)
+    }
+
+    pub fn get_mut(&mut self, id: u32) -> &mut Keyspace {
+        self.spaces.entry(id).or_default()
+    }
+
+    pub fn spaces(&self) -> impl Iterator<Item = (u32, &Keyspace)> {
+        self.spaces.iter().map(|(&id, space)| (id, space))
+    }
+
+    pub fn spaces_mut(&mut self) -> impl Iterator<Item = &mut Keyspace> {
+        self.spaces.values_mut()
+    }
+
+    pub fn set_spaces(&mut self, spaces: BTreeMap<u32, Keyspace>) {
+        self.spaces = spaces;
+    }
+}
+
+// 只读的命名空间句柄，由 ActionKV::namespace 或 Snapshot::namespace 得到
+// 判断过期用的时间固定在取得句柄的时刻
+#[derive(Debug)]
+pub struct Namespace<'a> {
+    pub(crate) name: String,
+    pub(crate) segments: &'a Segments,
+    pub(crate) keyspace: &'a Keyspace,
+    pub(crate) now: u64,
+    pub(crate) secret: Option<&'a EncryptionKey>,
+}
+
+impl Namespace<'_> {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn is_expired(&self, key: &ByteStr) -> bool {
+        self.keyspace.expires.get(key).is_some_and(|&at| at <= self.now)
+    }
+
+    pub fn get(&self, key: &ByteStr) -> io::Result<Option<ByteString>> {
+        let position = match self.keyspace.index.get(key) {
+            None => return Ok(None),
+            Some(position) => *position,
+        };
+        if self.is_expired(key) {
+            return Ok(None);
+        }
+        let record = log::read_at(self.segments, position)?;
+        record.into_value(self.secret).map(Some)
+    }
+
+    pub fn contains_key(&self, key: &ByteStr) -> bool {
+        self.keyspace.index.contains_key(key) && !self.is_expired(key)
+    }
+
+    pub fn scan<R: RangeBounds<ByteStr>>(&self, range: R) -> Scan<'_> {
+        self.scan_keys(self.keyspace.index.range(range))
+    }
+
+    pub fn prefix(&self, prefix: &ByteStr) -> Scan<'_> {
+        self.scan_keys(self.keyspace.index.range(scan::prefix_range(prefix)))
+    }
+
+    fn scan_keys<'a>(&'a self, keys: btree_map::Range<'a, ByteString, u64>) -> Scan<'a> {
+        Scan {
+            segments: self.segments,
+            keys,
+            expires: &self.keyspace.expires,
+            now: self.now,
+            secret: self.secret,
+        }
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &ByteString> {
+        self.keyspace.index.keys().filter(move |key| !self.is_expired(key))
+    }
+
+    // 导出这个命名空间，格式和 ActionKV::export 相同
+    pub fn export<W: io::Write>(&self, out: W, format: DumpFormat) -> io::Result<u64> {
+        dump::export(self.scan::<RangeFull>(..), &self.keyspace.expires, out, format)
+    }
+}
+
+// 可以写入的命名空间句柄，由 ActionKV::create_namespace 得到，用法和 ActionKV 相同
+#[derive(Debug)]
+pub struct NamespaceMut<'a> {
+    store: &'a mut ActionKV,
+    name: String,
+    id: u32,
+}
+
+impl<'a> NamespaceMut<'a> {
+    pub(crate) fn new(store: &'a mut ActionKV, name: &str, id: u32) -> Self {
+        NamespaceMut { store, name: name.to_string(), id }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get(&self, key: &ByteStr) -> io::Result<Option<ByteString>> {
+        self.store.get_in(self.id, key)
+    }
+
+    pub fn contains_key(&self, key: &ByteStr) -> bool {
+        self.store.position_in(self.id, key).is_some()
+    }
+
+    pub fn scan<R: RangeBounds<ByteStr>>(&self, range: R) -> Scan<'_> {
+        self.store.scan_in(self.id, range)
+    }
+
+    pub fn prefix(&self, prefix: &ByteStr) -> Scan<'_> {
+        self.store.prefix_in(self.id, prefix)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &ByteString> {
+        self.store.keys_in(self.id)
+    }
+
+    pub fn insert(&mut self, key: &ByteStr, value: &ByteStr) -> io::Result<()> {
+        self.store.put(self.id, key, value, None)
+    }
+
+    pub fn insert_with_ttl(
+        &mut self,
+        key: &ByteStr,
+        value: &ByteStr,
+        ttl: Duration,
+    ) -> io::Result<()> {
+        let expires = self.store.expires_after(ttl);
+        self.store.put(self.id, key, value, Some(expires))
+    }
+
+    pub fn delete(&mut self, key: &ByteStr) -> io::Result<()> {
+        self.store.remove(self.id, key)
+    }
+
+    // 只回收这个命名空间的旧记录，其他命名空间的记录原样保留，见 ActionKV::compact_namespace
+    pub fn compact(&mut self) -> io::Result<()> {
+        self.store.compact_namespace(self.id)
+    }
+
+    // 导出这个命名空间，格式和 ActionKV::export 相同
+    pub fn export<W: io::Write>(&self, out: W, format: DumpFormat) -> io::Result<u64> {
+        let (_, expires) = self.store.keyspace(self.id);
+        dump::export(self.scan::<RangeFull>(..), expires, out, format)
+    }
+
+    // 导入到这个命名空间
+    pub fn import<R: io::Read>(&mut self, input: R, format: DumpFormat) -> io::Result<u64> {
+        let (store, id) = (&mut *self.store, self.id);
+        dump::import(input, format, |key, value, expires| {
+            store.put(id, key, value, expires)
+        })
+    }
+}
@@ -0,0 +1,313 @@
+// 记录的编码与解码，格式见 lib.rs
+//
+// key_len 的高 8 位是记录标志（flags），低 24 位才是 key 的长度
+// 旧文件的 flags 恒为 0，因此仍然可以按原格式读取；旧文件中 value 为空的记录是删除，见 is_tombstone
+// flags 不为 0 时，checksum 同时覆盖 flags 字节，防止标志位被篡改而无法察觉
+//
+// 有些标志带有附加字段，附加字段放在 value 之前，计入 value_len，也在 checksum 覆盖的范围内
+//   FLAG_EXPIRES: expires u64，过期时间，自 UNIX 纪元起的毫秒数
+//   FLAG_VERSION: version u64，版本号，整个存储递增的序号，见 ActionKV::version
+//   FLAG_NAMESPACE: namespace u32，命名空间 id，没有这个标志的记录属于默认命名空间，见 namespace.rs
+// 多个附加字段按上面的顺序排列
+//
+// CODEC_MASK 位表示 value 的压缩编码，两位都为 0 表示没有压缩，见 compress.rs
+// FLAG_ENCRYPTED 表示 value 先压缩后加密，见 crypto.rs
+// 记录中保存的是压缩、加密后的 value，读取时才解密、解压
+
+use std::io;
+use std::io::prelude::*;
+
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::compress::{self, Compression};
+use crate::crypto::{self, EncryptionKey};
+use crate::{ByteStr, ByteString, Corruption, KeyValuePair, CRC};
+
+// 墓碑，表示该 key 已被删除
+pub(crate) const FLAG_TOMBSTONE: u8 = 0x01;
+// 批量写入的标记记录，见 batch.rs
+pub(crate) const FLAG_BATCH: u8 = 0x02;
+// 带有过期时间
+pub(crate) const FLAG_EXPIRES: u8 = 0x04;
+pub(crate) const FLAG_LZ4: u8 = 0x08;
+pub(crate) const FLAG_ZSTD: u8 = 0x10;
+pub(crate) const CODEC_MASK: u8 = FLAG_LZ4 | FLAG_ZSTD;
+pub(crate) const FLAG_ENCRYPTED: u8 = 0x20;
+// 带有版本号
+pub(crate) const FLAG_VERSION: u8 = 0x40;
+// 属于默认命名空间之外的命名空间；8 个标志位已经全部用完
+pub(crate) const FLAG_NAMESPACE: u8 = 0x80;
+
+const EXPIRES_LEN: u64 = 8;
+const VERSION_LEN: u64 = 8;
+const NAMESPACE_LEN: u64 = 4;
+
+pub(crate) const KEY_LEN_MASK: u32 = 0x00ff_ffff;
+pub(crate) const HEADER_LEN: u64 = 12;
+
+#[derive(Debug, Clone)]
+pub(crate) struct Record {
+    pub checksum: u32,
+    pub flags: u8,
+    pub key: ByteString,
+    // 磁盘上保存的 value，可能是压缩、加密过的，用 into_value 取得原始的 value
+    pub value: ByteString,
+    // 设置了 FLAG_EXPIRES 时的过期时间
+    pub expires: Option<u64>,
+    // 设置了 FLAG_VERSION 时的版本号
+    pub version: Option<u64>,
+    // 命名空间 id，默认命名空间为 0
+    pub namespace: u32,
+}
+
+impl Record {
+    pub fn new(flags: u8, key: &ByteStr, value: &ByteStr) -> io::Result<Record> {
+        if key.len() as u64 > KEY_LEN_MASK as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("key too long ({} bytes)", key.len()),
+            ));
+        }
+        let mut record = Record {
+            checksum: 0,
+            flags,
+            key: key.to_vec(),
+            value: value.to_vec(),
+            expires: None,
+            version: None,
+            namespace: 0,
+        };
+        record.checksum = record.compute_checksum();
+        Ok(record)
+    }
+
+    // 删除 key 的墓碑
+    pub fn tombstone(key: &ByteStr, version: u64) -> io::Result<Record> {
+        Ok(Record::new(FLAG_TOMBSTONE, key, b"")?.with_version(version))
+    }
+
+    // 设置过期时间，expires 是自 UNIX 纪元起的毫秒数
+    pub fn with_expiry(mut self, expires: u64) -> Record {
+        self.flags |= FLAG_EXPIRES;
+        self.expires = Some(expires);
+        self.checksum = self.compute_checksum();
+        self
+    }
+
+    pub fn with_version(mut self, version: u64) -> Record {
+        self.flags |= FLAG_VERSION;
+        self.version = Some(version);
+        self.checksum = self.compute_checksum();
+        self
+    }
+
+    // 放入命名空间 id，0 是默认命名空间，不设置标志
+    pub fn with_namespace(mut self, namespace: u32) -> Record {
+        if namespace != 0 {
+            self.flags |= FLAG_NAMESPACE;
+        }
+        self.namespace = namespace;
+        self.checksum = self.compute_checksum();
+        self
+    }
+
+    // 压缩 value，压缩后没有变小时保持原样
+    pub fn compressed(mut self, compression: Compression) -> io::Result<Record> {
+        let (codec, value) = compress::compress(compression, &self.value)?;
+        self.flags = self.flags & !CODEC_MASK | codec;
+        self.value = value;
+        self.checksum = self.compute_checksum();
+        Ok(self)
+    }
+
+    // 加密 value，必须在设置完其他标志和附加字段之后调用，它们都参与认证
+    pub fn encrypted(mut self, secret: &EncryptionKey) -> io::Result<Record> {
+        self.flags |= FLAG_ENCRYPTED;
+        self.value = crypto::encrypt(secret, &self.associated_data(), &self.value)?;
+        self.checksum = self.compute_checksum();
+        Ok(self)
+    }
+
+    // 加密时的关联数据：flags | key_len u32 | key | 附加字段
+    fn associated_data(&self) -> ByteString {
+        let mut aad = vec![self.flags];
+        aad.extend_from_slice(&(self.key.len() as u32).to_le_bytes());
+        aad.extend_from_slice(&self.key);
+        aad.extend_from_slice(&self.extra());
+        aad
+    }
+
+    // 解析位于 position 处的一条记录
+    // 记录不完整时返回 UnexpectedEof，校验失败时返回带 Corruption 的 InvalidData
+    pub fn read<R: Read>(f: &mut R, position: u64) -> io::Result<Record> {
+        // 以确定的方式读取磁盘数据
+        // 磁盘上 i32 的字节序可能因系统而异
+        let saved_checksum = f.read_u32::<LittleEndian>()?;
+        let key_len_field = f.read_u32::<LittleEndian>()?;
+        let flags = (key_len_field >> 24) as u8;
+        let key_len = key_len_field & KEY_LEN_MASK;
+        let val_len = f.read_u32::<LittleEndian>()?;
+        // 头部可能已经损坏，用 u64 避免溢出，也不能按它预先分配内存，读到多少分配多少
+        let data_len = key_len as u64 + val_len as u64;
+        let mut data = ByteString::new();
+        f.by_ref().take(data_len).read_to_end(&mut data)?;
+        if data.len() as u64 != data_len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("truncated record at offset {}", position),
+            ));
+        }
+
+        // 记录的头部不参与校验
+        let checksum = checksum(flags, &data);
+        if checksum != saved_checksum {
+            let corruption = Corruption {
+                offset: position,
+                len: HEADER_LEN + data_len,
+                expected: saved_checksum,
+                actual: checksum,
+            };
+            return Err(io::Error::new(io::ErrorKind::InvalidData, corruption));
+        }
+        if flags & CODEC_MASK == CODEC_MASK {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid record flags {:02x} at offset {}", flags, position),
+            ));
+        }
+
+        let mut value = data.split_off(key_len as usize);
+        let mut expires = None;
+        if flags & FLAG_EXPIRES != 0 {
+            expires = Some(take_u64(&mut value, EXPIRES_LEN, "expiry", position)?);
+        }
+        let mut version = None;
+        if flags & FLAG_VERSION != 0 {
+            version = Some(take_u64(&mut value, VERSION_LEN, "version", position)?);
+        }
+        let mut namespace = 0;
+        if flags & FLAG_NAMESPACE != 0 {
+            namespace = take_u64(&mut value, NAMESPACE_LEN, "namespace", position)? as u32;
+        }
+        Ok(Record { checksum, flags, key: data, value, expires, version, namespace })
+    }
+
+    // 按存储格式写入，返回写入的字节数
+    pub fn write<W: Write>(&self, f: &mut W) -> io::Result<u64> {
+        f.write_u32::<LittleEndian>(self.checksum)?;
+        f.write_u32::<LittleEndian>(self.key.len() as u32 | (self.flags as u32) << 24)?;
+        f.write_u32::<LittleEndian>(self.value_len() as u32)?;
+        f.write_all(&self.key)?;
+        f.write_all(&self.extra())?;
+        f.write_all(&self.value)?;
+        Ok(self.len())
+    }
+
+    pub fn len(&self) -> u64 {
+        HEADER_LEN + self.key.len() as u64 + self.value_len()
+    }
+
+    // 磁盘上 value_len 的值，包括附加字段
+    fn value_len(&self) -> u64 {
+        self.extra().len() as u64 + self.value.len() as u64
+    }
+
+    // 附加字段的编码
+    fn extra(&self) -> ByteString {
+        let mut extra = ByteString::new();
+        if let Some(expires) = self.expires {
+            extra.extend_from_slice(&expires.to_le_bytes());
+        }
+        if let Some(version) = self.version {
+            extra.extend_from_slice(&version.to_le_bytes());
+        }
+        if self.flags & FLAG_NAMESPACE != 0 {
+            extra.extend_from_slice(&self.namespace.to_le_bytes());
+        }
+        extra
+    }
+
+    fn compute_checksum(&self) -> u32 {
+        let mut data = ByteString::with_capacity(self.len() as usize);
+        data.extend_from_slice(&self.key);
+        data.extend_from_slice(&self.extra());
+        data.extend_from_slice(&self.value);
+        checksum(self.flags, &data)
+    }
+
+    // 墓碑，或者加入墓碑之前的删除：旧格式用空 value 表示删除
+    // 现在的写入都带版本号，flags 为 0 的空 value 只会来自旧文件；
+    // 加入版本号之前有意写入的空 value 因此也会读成删除
+    pub fn is_tombstone(&self) -> bool {
+        self.flags & FLAG_TOMBSTONE != 0 || (self.flags == 0 && self.value.is_empty())
+    }
+
+    pub fn is_batch_marker(&self) -> bool {
+        self.flags & FLAG_BATCH != 0
+    }
+
+    // 在 now 时是否已经过期
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    // 解密、解压后的 value
+    // 加密的记录在没有密钥时返回 PermissionDenied，密钥不对或数据被篡改时返回 InvalidData
+    pub fn into_value(self, secret: Option<&EncryptionKey>) -> io::Result<ByteString> {
+        self.decode(secret).map(|kv| kv.value)
+    }
+
+    pub fn into_kv(self, secret: Option<&EncryptionKey>) -> io::Result<KeyValuePair> {
+        self.decode(secret)
+    }
+
+    fn decode(self, secret: Option<&EncryptionKey>) -> io::Result<KeyValuePair> {
+        let tombstone = self.is_tombstone();
+        let value = if self.flags & FLAG_ENCRYPTED != 0 {
+            let secret = secret.ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "value is encrypted but no encryption key was given",
+                )
+            })?;
+            crypto::decrypt(secret, &self.associated_data(), &self.value)?
+        } else {
+            self.value
+        };
+        let value = compress::decompress(self.flags, value)?;
+        let (key, expires, version) = (self.key, self.expires, self.version);
+        Ok(KeyValuePair { key, value, tombstone, expires, version })
+    }
+}
+
+// 从 value 的开头取出一个 len 字节的整数附加字段
+fn take_u64(value: &mut ByteString, len: u64, name: &str, position: u64) -> io::Result<u64> {
+    if (value.len() as u64) < len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("missing {} at offset {}", name, position),
+        ));
+    }
+    let rest = value.split_off(len as usize);
+    let field = LittleEndian::read_uint(value, len as usize);
+    *value = rest;
+    Ok(field)
+}
+
+fn checksum(flags: u8, data: &ByteStr) -> u32 {
+    if flags == 0 {
+        return CRC.checksum(data);
+    }
+    let mut digest = CRC.digest();
+    digest.update(&[flags]);
+    digest.update(data);
+    digest.finalize()
+}
+
+// 只读取头部，得到整条记录的长度
+pub(crate) fn len_at<R: Read>(f: &mut R) -> io::Result<u64> {
+    let _checksum = f.read_u32::<LittleEndian>()?;
+    let key_len = f.read_u32::<LittleEndian>()? & KEY_LEN_MASK;
+    let val_len = f.read_u32::<LittleEndian>()?;
+    Ok(HEADER_LEN + key_len as u64 + val_len as u64)
+}
@@ -0,0 +1,315 @@
+// 主从复制：主库把日志中新提交的记录通过 TCP 发给一个或多个从库
+//
+// 从库连上之后先发送握手：魔数 AKVR | 已经复制到的主库位置 u64
+// 主库从这个位置订阅自己的日志（见 subscribe.rs），依次发送帧：
+//   R | 位置 u64 | 长度 u32 | 记录    一条已提交的记录，和日志中的字节相同，带有 CRC
+//   P | 位置 u64                     这个位置之前的记录都已经发出；追上末尾时也作为心跳定期发送
+//   E | 长度 u32 | 消息              主库无法继续发送，随后关闭连接
+// 批次的成员只在读到 commit 之后发送，P 只落在完整的批次之后
+// R 和 E 的长度不超过 MAX_FRAME_LEN，从库按长度分配内存，不能让出错或恶意的主库随意指定
+//
+// 从库收到 P 时把之前的记录作为一组写入自己的日志（多于一条时作为一个批次）并落盘，
+// 然后把 P 的位置写入 replica 辅助数据（文件后端是 replica 文件），断开或重启后从这里继续；
+// 落盘和记下位置之间崩溃，重连后会再收到这一组记录，重复写入不改变结果
+//
+// 记录原样写入，保留主库的版本号和过期时间；加密的记录需要从库用相同的密钥打开
+// 主库压缩后位置全部改变，从库会收到 E，需要用主库的备份重建
+// 从库只能读，promote 停止复制之后才能写入；akv_server --follow 收到 REPLICAOF NO ONE 时提升
+
+use std::io;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::record::Record;
+use crate::server;
+use crate::storage::Storage;
+use crate::{ActionKV, ByteStr, ByteString, SharedActionKV};
+
+const MAGIC: &[u8; 4] = b"AKVR";
+
+const FRAME_RECORD: u8 = b'R';
+const FRAME_POSITION: u8 = b'P';
+const FRAME_ERROR: u8 = b'E';
+
+// R 帧中记录和 E 帧中消息的最大长度，更长的记录不能复制
+const MAX_FRAME_LEN: u32 = 64 << 20;
+
+// 复制到的位置在 Storage 中的名字
+const OFFSET_NAME: &str = "replica";
+
+// 主库追上日志末尾后再次读取的间隔，也是心跳的间隔
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+// 从库断开后重新连接的间隔
+const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+// 接受从库的连接并为每个从库启动一个线程，只在 listener 出错时返回
+pub fn serve_replication(listener: TcpListener, store: SharedActionKV) -> io::Result<()> {
+    // 从库断开只影响这个连接，从库会重新连接
+    server::accept_loop(listener, move |stream| ship(stream, &store))
+}
+
+// 向一个从库发送记录，直到连接断开
+fn ship(stream: TcpStream, store: &SharedActionKV) -> io::Result<()> {
+    let mut magic = [0; 4];
+    (&stream).read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not an actionkv replica"));
+    }
+    let from = (&stream).read_u64::<LittleEndian>()?;
+
+    let mut out = BufWriter::new(stream);
+    let mut feed = match store.subscribe(from) {
+        Ok(feed) => feed,
+        Err(err) => return refuse(&mut out, err),
+    };
+    let mut buf = ByteString::new();
+    loop {
+        match feed.next_record() {
+            Some(Ok((position, record))) => {
+                buf.clear();
+                record.write(&mut buf)?;
+                if buf.len() > MAX_FRAME_LEN as usize {
+                    let message = format!("record at {} is too large to replicate", position);
+                    return refuse(&mut out, io::Error::new(io::ErrorKind::InvalidData, message));
+                }
+                out.write_u8(FRAME_RECORD)?;
+                out.write_u64::<LittleEndian>(position)?;
+                out.write_u32::<LittleEndian>(buf.len() as u32)?;
+                out.write_all(&buf)?;
+                // 订阅的位置只在完整交付一个批次之后前进
+                if feed.position() > position {
+                    out.write_u8(FRAME_POSITION)?;
+                    out.write_u64::<LittleEndian>(feed.position())?;
+                }
+            }
+            Some(Err(err)) => return refuse(&mut out, err),
+            None => {
+                // 心跳：确认发送到的位置，从库断开时写入会失败，线程随之退出
+                out.write_u8(FRAME_POSITION)?;
+                out.write_u64::<LittleEndian>(feed.position())?;
+                out.flush()?;
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+    }
+}
+
+// 告诉从库无法继续，返回原来的错误
+fn refuse<W: Write>(out: &mut W, err: io::Error) -> io::Result<()> {
+    let message = err.to_string();
+    out.write_u8(FRAME_ERROR)?;
+    out.write_u32::<LittleEndian>(message.len() as u32)?;
+    out.write_all(message.as_bytes())?;
+    out.flush()?;
+    Err(err)
+}
+
+// 复制线程和 Follower 共享的状态
+#[derive(Debug, Default)]
+struct State {
+    stopped: AtomicBool,
+    // 已经写入并记下的主库位置
+    offset: AtomicU64,
+    // 当前的连接，promote 时关闭它来打断阻塞的读取
+    connection: Mutex<Option<TcpStream>>,
+    error: Mutex<Option<String>>,
+}
+
+impl State {
+    fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+
+    fn set_error(&self, error: Option<String>) {
+        *self.error.lock().expect("replica state poisoned") = error;
+    }
+}
+
+// 从库：在后台线程中跟随主库，直到 promote 或被丢弃
+#[derive(Debug)]
+pub struct Follower {
+    store: SharedActionKV,
+    state: Arc<State>,
+    storage: Arc<dyn Storage>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Follower {
+    // 开始从 leader 复制，store 应该已经 load 过
+    // 从 replica 文件中记下的位置继续，没有这个文件时从头复制
+    pub fn start(store: ActionKV, leader: SocketAddr) -> io::Result<Self> {
+        let storage = store.log.storage().clone();
+        let state = Arc::new(State::default());
+        state.offset.store(read_offset(storage.as_ref())?, Ordering::SeqCst);
+        let store = SharedActionKV::from(store);
+
+        let thread = {
+            let (store, state) = (store.clone(), state.clone());
+            let storage = storage.clone();
+            thread::Builder::new()
+                .name("actionkv-replica".to_string())
+                .spawn(move || run(leader, &store, &state, storage.as_ref()))?
+        };
+        Ok(Follower { store, state, storage, thread: Some(thread) })
+    }
+
+    // 已经复制到的主库位置，等于主库日志末尾时说明已经追上
+    pub fn offset(&self) -> u64 {
+        self.state.offset.load(Ordering::SeqCst)
+    }
+
+    // 最近一次复制失败的原因，之后成功收到主库的位置时清除
+    pub fn last_error(&self) -> Option<String> {
+        self.state.error.lock().expect("replica state poisoned").clone()
+    }
+
+    pub fn get(&self, key: &ByteStr) -> io::Result<Option<ByteString>> {
+        self.store.get(key)
+    }
+
+    // 从库的存储，只在提升之前用来读取，见 server::serve_follower
+    pub(crate) fn store(&self) -> &SharedActionKV {
+        &self.store
+    }
+
+    // 在读锁下访问从库的 ActionKV，复制线程的写入在这期间等待
+    pub fn read<T>(&self, f: impl FnOnce(&ActionKV) -> T) -> T {
+        self.store.read(f)
+    }
+
+    // 停止复制并把从库变成可以写入的存储
+    // 删除 replica 文件，提升之后的存储和主库已经分叉，不能再从原来的位置跟随
+    pub fn promote(mut self) -> io::Result<SharedActionKV> {
+        self.stop();
+        self.storage.remove_sidecar(OFFSET_NAME)?;
+        Ok(self.store.clone())
+    }
+
+    fn stop(&mut self) {
+        self.state.stopped.store(true, Ordering::SeqCst);
+        if let Some(connection) = &*self.state.connection.lock().expect("replica state poisoned") {
+            let _ = connection.shutdown(Shutdown::Both);
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Follower {
+    // 只停止复制，replica 文件留着，下次 start 从这里继续
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+// 复制线程的主循环：断开后等一会儿重新连接
+fn run(leader: SocketAddr, store: &SharedActionKV, state: &State, storage: &dyn Storage) {
+    while !state.is_stopped() {
+        if let Err(err) = follow(leader, store, state, storage) {
+            if !state.is_stopped() {
+                state.set_error(Some(err.to_string()));
+            }
+        }
+        *state.connection.lock().expect("replica state poisoned") = None;
+        if !state.is_stopped() {
+            thread::sleep(RETRY_INTERVAL);
+        }
+    }
+}
+
+// 连接主库并应用收到的记录，直到连接断开
+fn follow(
+    leader: SocketAddr,
+    store: &SharedActionKV,
+    state: &State,
+    storage: &dyn Storage,
+) -> io::Result<()> {
+    let stream = TcpStream::connect(leader)?;
+    *state.connection.lock().expect("replica state poisoned") = Some(stream.try_clone()?);
+    // stop 可能在连接保存之前就检查过 connection
+    if state.is_stopped() {
+        return Ok(());
+    }
+
+    let mut offset = state.offset.load(Ordering::SeqCst);
+    let mut handshake = MAGIC.to_vec();
+    handshake.write_u64::<LittleEndian>(offset)?;
+    (&stream).write_all(&handshake)?;
+
+    let mut input = BufReader::new(stream);
+    // 收到、还没有写入的一组记录
+    let mut group = Vec::new();
+    loop {
+        match input.read_u8()? {
+            FRAME_RECORD => {
+                let position = input.read_u64::<LittleEndian>()?;
+                let buf = read_frame(&mut input)?;
+                group.push(Record::read(&mut buf.as_slice(), position)?);
+            }
+            FRAME_POSITION => {
+                let position = input.read_u64::<LittleEndian>()?;
+                if !group.is_empty() {
+                    store.write(|kv| kv.apply_replicated(std::mem::take(&mut group)))?;
+                }
+                if position != offset {
+                    write_offset(storage, position)?;
+                    offset = position;
+                    state.offset.store(position, Ordering::SeqCst);
+                }
+                state.set_error(None);
+            }
+            FRAME_ERROR => {
+                let message = read_frame(&mut input)?;
+                let message = String::from_utf8_lossy(&message);
+                return Err(io::Error::other(format!("leader: {}", message)));
+            }
+            kind => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unknown replication frame {:#04x}", kind),
+                ));
+            }
+        }
+    }
+}
+
+// 读取 长度 u32 | 数据，长度超过 MAX_FRAME_LEN 时不分配内存，直接返回 InvalidData
+fn read_frame<R: Read>(input: &mut R) -> io::Result<ByteString> {
+    let len = input.read_u32::<LittleEndian>()?;
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("replication frame of {} bytes is too large", len),
+        ));
+    }
+    let mut buf = vec![0; len as usize];
+    input.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+// 没有记下位置时从 0 开始
+fn read_offset(storage: &dyn Storage) -> io::Result<u64> {
+    let buf = match storage.read_sidecar(OFFSET_NAME)? {
+        Some(buf) => buf,
+        None => return Ok(0),
+    };
+    let mut buf = buf.as_slice();
+    let offset = buf.read_u64::<LittleEndian>()?;
+    if !buf.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid replica file"));
+    }
+    Ok(offset)
+}
+
+// 和 hint 一样原子地替换
+fn write_offset(storage: &dyn Storage, offset: u64) -> io::Result<()> {
+    storage.write_sidecar(OFFSET_NAME, &offset.to_le_bytes())
+}
@@ -0,0 +1,144 @@
+// RESP（Redis 的通信协议）的编码与解码
+//
+// 请求是由 bulk string 组成的数组：*2\r\n$3\r\nGET\r\n$1\r\na\r\n
+// 也接受按空白分隔的单行命令（inline command），方便用 telnet 调试
+// 回复的第一个字节表示类型：+ 简单字符串，- 错误，: 整数，$ bulk string，* 数组
+
+use std::io;
+use std::io::prelude::*;
+
+use crate::ByteString;
+
+// 与 redis 的默认限制相同，避免按对方给出的长度分配过多内存
+const MAX_BULK_LEN: u64 = 512 * 1024 * 1024;
+const MAX_ARRAY_LEN: i64 = 1024 * 1024;
+const MAX_INLINE_LEN: u64 = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Reply {
+    Simple(&'static str),
+    Error(String),
+    Integer(i64),
+    Bulk(ByteString),
+    Null,
+    Array(Vec<Reply>),
+}
+
+impl Reply {
+    pub fn error(message: impl Into<String>) -> Reply {
+        Reply::Error(message.into())
+    }
+
+    pub fn write<W: Write>(&self, f: &mut W) -> io::Result<()> {
+        match self {
+            Reply::Simple(s) => write!(f, "+{}\r\n", s),
+            // 错误信息中的换行会破坏协议，替换成空格
+            Reply::Error(message) => write!(f, "-{}\r\n", message.replace(['\r', '\n'], " ")),
+            Reply::Integer(n) => write!(f, ":{}\r\n", n),
+            Reply::Bulk(data) => {
+                write!(f, "${}\r\n", data.len())?;
+                f.write_all(data)?;
+                f.write_all(b"\r\n")
+            }
+            Reply::Null => f.write_all(b"$-1\r\n"),
+            Reply::Array(items) => {
+                write!(f, "*{}\r\n", items.len())?;
+                for item in items {
+                    item.write(f)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+// 读取一条命令，连接正常关闭时返回 None
+// 格式错误时返回 InvalidData，调用者应回复错误并关闭连接
+pub(crate) fn read_command<R: BufRead>(f: &mut R) -> io::Result<Option<Vec<ByteString>>> {
+    loop {
+        let line = match read_line(f)? {
+            None => return Ok(None),
+            Some(line) => line,
+        };
+        if line.first() != Some(&b'*') {
+            let args: Vec<ByteString> = line
+                .split(|b| b.is_ascii_whitespace())
+                .filter(|arg| !arg.is_empty())
+                .map(|arg| arg.to_vec())
+                .collect();
+            // 空行直接忽略
+            if args.is_empty() {
+                continue;
+            }
+            return Ok(Some(args));
+        }
+
+        let count = parse_len(&line[1..])?;
+        if count > MAX_ARRAY_LEN {
+            return Err(protocol_error("invalid multibulk length"));
+        }
+        let mut args = Vec::with_capacity(count.max(0) as usize);
+        for _ in 0..count {
+            args.push(read_bulk(f)?);
+        }
+        if args.is_empty() {
+            continue;
+        }
+        return Ok(Some(args));
+    }
+}
+
+fn read_bulk<R: BufRead>(f: &mut R) -> io::Result<ByteString> {
+    let line = read_line(f)?.ok_or_else(eof)?;
+    if line.first() != Some(&b'$') {
+        return Err(protocol_error("expected '$'"));
+    }
+    let len = parse_len(&line[1..])?;
+    if len < 0 || len as u64 > MAX_BULK_LEN {
+        return Err(protocol_error("invalid bulk length"));
+    }
+    let mut data = ByteString::new();
+    f.by_ref().take(len as u64 + 2).read_to_end(&mut data)?;
+    if data.len() as i64 != len + 2 {
+        return Err(eof());
+    }
+    if !data.ends_with(b"\r\n") {
+        return Err(protocol_error("bulk string is not terminated by CRLF"));
+    }
+    data.truncate(len as usize);
+    Ok(data)
+}
+
+// 读取一行，去掉行尾的 \r\n（或 \n）
+fn read_line<R: BufRead>(f: &mut R) -> io::Result<Option<ByteString>> {
+    let mut line = ByteString::new();
+    f.by_ref().take(MAX_INLINE_LEN).read_until(b'\n', &mut line)?;
+    if line.is_empty() {
+        return Ok(None);
+    }
+    if line.pop() != Some(b'\n') {
+        if line.len() as u64 + 1 >= MAX_INLINE_LEN {
+            return Err(protocol_error("line too long"));
+        }
+        return Err(eof());
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    Ok(Some(line))
+}
+
+fn parse_len(digits: &[u8]) -> io::Result<i64> {
+    std::str::from_utf8(digits)
+        .ok()
+        .and_then(|digits| digits.parse().ok())
+        .ok_or_else(|| protocol_error("invalid length"))
+}
+
+fn protocol_error(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Protocol error: {}", message))
+}
+
+fn eof() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed in the middle of a command")
+}
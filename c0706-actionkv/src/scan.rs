@@ -0,0 +1,46 @@
+// 按 key 的顺序遍历，value 在迭代时才从磁盘读取，跳过已经过期的 key
+
+use std::collections::btree_map;
+use std::io;
+use std::ops::Bound;
+
+use crate::log::{self, Segments};
+use crate::{ByteStr, ByteString, EncryptionKey, Expires};
+
+pub struct Scan<'a> {
+    pub(crate) segments: &'a Segments,
+    pub(crate) keys: btree_map::Range<'a, ByteString, u64>,
+    pub(crate) expires: &'a Expires,
+    // 开始遍历时的时间，遍历过程中过期的 key 仍然返回
+    pub(crate) now: u64,
+    pub(crate) secret: Option<&'a EncryptionKey>,
+}
+
+impl Iterator for Scan<'_> {
+    type Item = io::Result<(ByteString, ByteString)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, &position) = loop {
+            let (key, position) = self.keys.next()?;
+            if self.expires.get(key).is_none_or(|&at| at > self.now) {
+                break (key, position);
+            }
+        };
+        let value = log::read_at(self.segments, position)
+            .and_then(|record| record.into_value(self.secret));
+        let value = value.map(|value| (key.clone(), value));
+        Some(value)
+    }
+}
+
+// 以 prefix 开头的 key 的范围：[prefix, 把 prefix 最后一个不是 0xff 的字节加一)
+pub(crate) fn prefix_range(prefix: &ByteStr) -> (Bound<ByteString>, Bound<ByteString>) {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < 0xff {
+            end.push(last + 1);
+            return (Bound::Included(prefix.to_vec()), Bound::Excluded(end));
+        }
+    }
+    (Bound::Included(prefix.to_vec()), Bound::Unbounded)
+}
@@ -0,0 +1,299 @@
+// 用 RESP 协议提供 ActionKV 服务，redis-cli 等 redis 客户端可以直接连接
+// 每个连接一个线程，共享同一个 SharedActionKV：读可以并发，写依次执行
+//
+// 支持的命令：PING GET SET DEL EXISTS SCAN QUIT REPLICAOF
+//
+// serve_follower 以从库模式提供服务：读取跟随主库的数据，SET 和 DEL 返回 READONLY，
+// 客户端发送 REPLICAOF NO ONE 时停止复制，提升为可以写入的主库（见 Follower::promote）
+
+use std::io;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::replication::Follower;
+use crate::resp::{self, Reply};
+use crate::{ByteStr, ByteString, SharedActionKV};
+
+// SCAN 没有指定 COUNT 时每次返回的 key 数，与 redis 相同
+const DEFAULT_SCAN_COUNT: usize = 10;
+
+// 所有连接共享的状态
+struct Service {
+    store: SharedActionKV,
+    // 从库模式下正在复制的从库，提升之后是 None，主库一直是 None
+    follower: Mutex<Option<Follower>>,
+}
+
+impl Service {
+    fn is_follower(&self) -> bool {
+        self.follower.lock().expect("follower lock poisoned").is_some()
+    }
+}
+
+// 接受连接并为每个连接启动一个线程，只在 listener 出错时返回
+pub fn serve(listener: TcpListener, store: SharedActionKV) -> io::Result<()> {
+    let service = Service { store, follower: Mutex::new(None) };
+    accept_loop(listener, move |stream| handle(stream, &service))
+}
+
+// 以从库模式提供服务，直到 listener 出错
+pub fn serve_follower(listener: TcpListener, follower: Follower) -> io::Result<()> {
+    let store = follower.store().clone();
+    let service = Service { store, follower: Mutex::new(Some(follower)) };
+    accept_loop(listener, move |stream| handle(stream, &service))
+}
+
+// 接受连接，在新线程中用 handler 处理每个连接，只在 listener 出错时返回
+// 复制服务也使用它；连接上的 I/O 错误只影响这个连接，handler 的错误被忽略
+pub(crate) fn accept_loop<F>(listener: TcpListener, handler: F) -> io::Result<()>
+where
+    F: Fn(TcpStream) -> io::Result<()> + Send + Sync + 'static,
+{
+    let handler = Arc::new(handler);
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            // 客户端在 accept 之前就断开了，不影响其他连接
+            Err(err) if err.kind() == io::ErrorKind::ConnectionAborted => continue,
+            Err(err) => return Err(err),
+        };
+        let handler = handler.clone();
+        thread::spawn(move || {
+            let _ = handler(stream);
+        });
+    }
+    Ok(())
+}
+
+fn handle(stream: TcpStream, service: &Service) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    loop {
+        let args = match resp::read_command(&mut reader) {
+            Ok(Some(args)) => args,
+            Ok(None) => return Ok(()),
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                // 协议错误之后无法确定下一条命令从哪里开始，只能关闭连接
+                Reply::error(format!("ERR {}", err)).write(&mut writer)?;
+                return writer.flush();
+            }
+            Err(err) => return Err(err),
+        };
+
+        let quit = args[0].eq_ignore_ascii_case(b"QUIT");
+        execute(service, &args).write(&mut writer)?;
+        // 客户端用流水线一次发来多条命令时，全部执行完再一起发送回复
+        if quit || reader.buffer().is_empty() {
+            writer.flush()?;
+        }
+        if quit {
+            return Ok(());
+        }
+    }
+}
+
+fn execute(service: &Service, args: &[ByteString]) -> Reply {
+    let store = &service.store;
+    let name = String::from_utf8_lossy(&args[0]).to_ascii_lowercase();
+    let args = &args[1..];
+    // 参数个数的范围，不含命令名
+    let (min, max) = match name.as_str() {
+        "ping" => (0, 1),
+        "get" => (1, 1),
+        "set" => (2, usize::MAX),
+        "del" | "exists" => (1, usize::MAX),
+        "scan" => (1, usize::MAX),
+        "replicaof" => (2, 2),
+        "quit" => (0, usize::MAX),
+        _ => return Reply::error(format!("ERR unknown command '{}'", name)),
+    };
+    if args.len() < min || args.len() > max {
+        return Reply::error(format!("ERR wrong number of arguments for '{}' command", name));
+    }
+    // 从库的数据只来自主库，提升之前不接受写入
+    if matches!(name.as_str(), "set" | "del") && service.is_follower() {
+        return Reply::error("READONLY You can't write against a read only replica.");
+    }
+
+    let result = match name.as_str() {
+        "ping" => Ok(ping(args)),
+        "get" => get(store, &args[0]),
+        "set" => set(store, args),
+        "del" => del(store, args),
+        "exists" => Ok(exists(store, args)),
+        "scan" => scan(store, args),
+        "replicaof" => replicaof(service, args),
+        _ => Ok(Reply::Simple("OK")),
+    };
+    result.unwrap_or_else(|err| Reply::error(format!("ERR {}", err)))
+}
+
+fn ping(args: &[ByteString]) -> Reply {
+    match args.first() {
+        None => Reply::Simple("PONG"),
+        Some(message) => Reply::Bulk(message.clone()),
+    }
+}
+
+fn get(store: &SharedActionKV, key: &ByteStr) -> io::Result<Reply> {
+    match store.get(key)? {
+        None => Ok(Reply::Null),
+        Some(value) => Ok(Reply::Bulk(value)),
+    }
+}
+
+// SET key value [EX seconds | PX milliseconds]
+fn set(store: &SharedActionKV, args: &[ByteString]) -> io::Result<Reply> {
+    let ttl = match &args[2..] {
+        [] => None,
+        [unit, amount] => {
+            let amount = match parse_number(amount) {
+                Some(amount) if amount > 0 => amount as u64,
+                _ => return Ok(Reply::error("ERR invalid expire time in 'set' command")),
+            };
+            if unit.eq_ignore_ascii_case(b"EX") {
+                Some(Duration::from_secs(amount))
+            } else if unit.eq_ignore_ascii_case(b"PX") {
+                Some(Duration::from_millis(amount))
+            } else {
+                return Ok(Reply::error("ERR syntax error"));
+            }
+        }
+        _ => return Ok(Reply::error("ERR syntax error")),
+    };
+    match ttl {
+        None => store.insert(&args[0], &args[1])?,
+        Some(ttl) => store.insert_with_ttl(&args[0], &args[1], ttl)?,
+    }
+    Ok(Reply::Simple("OK"))
+}
+
+// 返回实际删除的 key 数，不存在的 key 不写墓碑
+fn del(store: &SharedActionKV, keys: &[ByteString]) -> io::Result<Reply> {
+    store.write(|kv| {
+        let mut deleted = 0;
+        for key in keys {
+            if kv.contains_key(key) {
+                kv.delete(key)?;
+                deleted += 1;
+            }
+        }
+        Ok(Reply::Integer(deleted))
+    })
+}
+
+// 与 redis 相同，重复的 key 重复计数
+fn exists(store: &SharedActionKV, keys: &[ByteString]) -> Reply {
+    let found = store.read(|kv| keys.iter().filter(|key| kv.contains_key(key)).count());
+    Reply::Integer(found as i64)
+}
+
+// SCAN cursor [MATCH pattern] [COUNT count]
+// cursor 是已经遍历过的 key 的个数，0 表示开始或结束
+// 遍历期间有 key 被删除时，之后的 key 可能被跳过或重复返回
+fn scan(store: &SharedActionKV, args: &[ByteString]) -> io::Result<Reply> {
+    let cursor = match parse_number(&args[0]) {
+        Some(cursor) => cursor,
+        None => return Ok(Reply::error("ERR invalid cursor")),
+    };
+    let mut pattern = None;
+    let mut count = DEFAULT_SCAN_COUNT;
+    for option in args[1..].chunks(2) {
+        match option {
+            [name, value] if name.eq_ignore_ascii_case(b"MATCH") => pattern = Some(value),
+            [name, value] if name.eq_ignore_ascii_case(b"COUNT") => {
+                count = match parse_number(value) {
+                    Some(count) if count > 0 => count,
+                    _ => return Ok(Reply::error("ERR value is not an integer or out of range")),
+                };
+            }
+            _ => return Ok(Reply::error("ERR syntax error")),
+        }
+    }
+
+    let (next, keys) = store.read(|kv| {
+        let mut visited = 0;
+        let mut keys = Vec::new();
+        for key in kv.keys().skip(cursor).take(count) {
+            visited += 1;
+            if pattern.is_none_or(|pattern| glob_match(pattern, key)) {
+                keys.push(Reply::Bulk(key.clone()));
+            }
+        }
+        let done = visited < count;
+        (if done { 0 } else { cursor + visited }, keys)
+    });
+    let cursor = Reply::Bulk(next.to_string().into_bytes());
+    Ok(Reply::Array(vec![cursor, Reply::Array(keys)]))
+}
+
+// REPLICAOF NO ONE：停止复制并提升为主库，已经是主库时什么也不做
+// 不支持在运行时改为跟随另一个主库
+fn replicaof(service: &Service, args: &[ByteString]) -> io::Result<Reply> {
+    if !(args[0].eq_ignore_ascii_case(b"NO") && args[1].eq_ignore_ascii_case(b"ONE")) {
+        return Ok(Reply::error("ERR only REPLICAOF NO ONE is supported"));
+    }
+    // 复制线程停下之前一直持有锁，其他连接的写入等到提升完成之后才执行
+    let mut follower = service.follower.lock().expect("follower lock poisoned");
+    if let Some(follower) = follower.take() {
+        follower.promote()?;
+    }
+    Ok(Reply::Simple("OK"))
+}
+
+fn parse_number(arg: &ByteStr) -> Option<usize> {
+    std::str::from_utf8(arg).ok()?.parse().ok()
+}
+
+// redis 风格的通配符：* 任意个字节，? 一个字节，[abc] [^abc] [a-z] 字符集，\ 转义
+fn glob_match(pattern: &ByteStr, text: &ByteStr) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) => (0..=text.len()).any(|skip| glob_match(rest, &text[skip..])),
+        Some((b'?', rest)) => !text.is_empty() && glob_match(rest, &text[1..]),
+        Some((b'[', rest)) => {
+            let Some((&c, text_rest)) = text.split_first() else {
+                return false;
+            };
+            match match_class(rest, c) {
+                Some((true, rest)) => glob_match(rest, text_rest),
+                _ => false,
+            }
+        }
+        Some((b'\\', rest)) if !rest.is_empty() => {
+            text.first() == Some(&rest[0]) && glob_match(&rest[1..], &text[1..])
+        }
+        Some((&p, rest)) => text.first() == Some(&p) && glob_match(rest, &text[1..]),
+    }
+}
+
+// 匹配 [ 之后的字符集，返回是否匹配以及 ] 之后的模式，缺少 ] 时返回 None
+fn match_class(mut pattern: &ByteStr, c: u8) -> Option<(bool, &ByteStr)> {
+    let negate = pattern.first() == Some(&b'^');
+    if negate {
+        pattern = &pattern[1..];
+    }
+    let mut matched = false;
+    loop {
+        match pattern {
+            [] => return None,
+            [b']', rest @ ..] => return Some((matched != negate, rest)),
+            [b'\\', x, rest @ ..] => {
+                matched |= *x == c;
+                pattern = rest;
+            }
+            [lo, b'-', hi, rest @ ..] if *hi != b']' => {
+                matched |= (*lo.min(hi)..=*lo.max(hi)).contains(&c);
+                pattern = rest;
+            }
+            [x, rest @ ..] => {
+                matched |= *x == c;
+                pattern = rest;
+            }
+        }
+    }
+}
@@ -0,0 +1,158 @@
+// 可以在线程间共享的 ActionKV 句柄
+// 读取用 pread，不依赖文件的读写位置，持有读锁就能并发执行；
+// 写入持有写锁，同一时刻只有一个写者
+// 写者在写锁下完成 fsync，Durability::Always 时每次写入期间读者都要等待；
+// 不想被写者挡住的读者可以先取 snapshot，它只在创建时短暂持有读锁，之后的读取不需要锁
+
+use std::io;
+use std::ops::RangeBounds;
+use std::path::Path;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
+
+use crate::{
+    ActionKV, BackupReport, ByteStr, ByteString, Options, Snapshot, Subscription, WriteBatch,
+};
+
+#[derive(Debug, Clone)]
+pub struct SharedActionKV {
+    inner: Arc<RwLock<ActionKV>>,
+}
+
+impl SharedActionKV {
+    // 打开并加载存储
+    pub fn open(path: &Path) -> io::Result<Self> {
+        SharedActionKV::open_with(path, Options::default())
+    }
+
+    pub fn open_with(path: &Path, options: Options) -> io::Result<Self> {
+        let mut store = ActionKV::open_with(path, options)?;
+        store.load()?;
+        Ok(SharedActionKV::from(store))
+    }
+
+    pub fn get(&self, key: &ByteStr) -> io::Result<Option<ByteString>> {
+        self.read_lock().get(key)
+    }
+
+    pub fn contains_key(&self, key: &ByteStr) -> bool {
+        self.read_lock().contains_key(key)
+    }
+
+    // 迭代器不能越过锁返回，这里直接收集成 Vec
+    pub fn scan<R>(&self, range: R) -> io::Result<Vec<(ByteString, ByteString)>>
+    where
+        R: RangeBounds<ByteStr>,
+    {
+        self.read_lock().scan(range).collect()
+    }
+
+    pub fn prefix(&self, prefix: &ByteStr) -> io::Result<Vec<(ByteString, ByteString)>> {
+        self.read_lock().prefix(prefix).collect()
+    }
+
+    pub fn keys(&self) -> Vec<ByteString> {
+        self.read_lock().keys().cloned().collect()
+    }
+
+    // 快照不持有锁，创建之后可以在锁外慢慢遍历
+    pub fn snapshot(&self) -> io::Result<Snapshot> {
+        self.read_lock().snapshot()
+    }
+
+    // 只在创建快照时持有读锁，写入备份时写者不受影响
+    pub fn backup(&self, dest: &Path) -> io::Result<BackupReport> {
+        self.snapshot()?.backup(dest)
+    }
+
+    pub fn subscribe(&self, from: u64) -> io::Result<Subscription> {
+        self.read_lock().subscribe(from)
+    }
+
+    pub fn get_as_of(&self, key: &ByteStr, position: u64) -> io::Result<Option<ByteString>> {
+        self.read_lock().get_as_of(key, position)
+    }
+
+    pub fn version(&self, key: &ByteStr) -> io::Result<Option<u64>> {
+        self.read_lock().version(key)
+    }
+
+    pub fn get_with_version(&self, key: &ByteStr) -> io::Result<Option<(ByteString, u64)>> {
+        self.read_lock().get_with_version(key)
+    }
+
+    // 比较版本和写入在同一次持有写锁期间完成，其他写者不会插在中间
+    pub fn insert_if_absent(&self, key: &ByteStr, value: &ByteStr) -> io::Result<u64> {
+        self.write_lock().insert_if_absent(key, value)
+    }
+
+    pub fn update_if_version(
+        &self,
+        key: &ByteStr,
+        expected: u64,
+        value: &ByteStr,
+    ) -> io::Result<u64> {
+        self.write_lock().update_if_version(key, expected, value)
+    }
+
+    pub fn delete_if_version(&self, key: &ByteStr, expected: u64) -> io::Result<()> {
+        self.write_lock().delete_if_version(key, expected)
+    }
+
+    pub fn insert(&self, key: &ByteStr, value: &ByteStr) -> io::Result<()> {
+        self.write_lock().insert(key, value)
+    }
+
+    pub fn insert_with_ttl(&self, key: &ByteStr, value: &ByteStr, ttl: Duration) -> io::Result<()> {
+        self.write_lock().insert_with_ttl(key, value, ttl)
+    }
+
+    pub fn update(&self, key: &ByteStr, value: &ByteStr) -> io::Result<()> {
+        self.write_lock().update(key, value)
+    }
+
+    pub fn delete(&self, key: &ByteStr) -> io::Result<()> {
+        self.write_lock().delete(key)
+    }
+
+    pub fn write_batch(&self, batch: &WriteBatch) -> io::Result<()> {
+        self.write_lock().write_batch(batch)
+    }
+
+    pub fn flush(&self) -> io::Result<()> {
+        self.write_lock().flush()
+    }
+
+    pub fn sync(&self) -> io::Result<()> {
+        self.write_lock().sync()
+    }
+
+    pub fn compact(&self) -> io::Result<()> {
+        self.write_lock().compact()
+    }
+
+    // 在读锁下访问 ActionKV，用于上面没有包装的只读操作
+    pub fn read<T>(&self, f: impl FnOnce(&ActionKV) -> T) -> T {
+        f(&self.read_lock())
+    }
+
+    // 在写锁下访问 ActionKV
+    pub fn write<T>(&self, f: impl FnOnce(&mut ActionKV) -> T) -> T {
+        f(&mut self.write_lock())
+    }
+
+    fn read_lock(&self) -> RwLockReadGuard<'_, ActionKV> {
+        self.inner.read().expect("actionkv lock poisoned")
+    }
+
+    fn write_lock(&self) -> RwLockWriteGuard<'_, ActionKV> {
+        self.inner.write().expect("actionkv lock poisoned")
+    }
+}
+
+impl From<ActionKV> for SharedActionKV {
+    // store 应该已经 load 过
+    fn from(store: ActionKV) -> Self {
+        SharedActionKV { inner: Arc::new(RwLock::new(store)) }
+    }
+}
@@ -0,0 +1,141 @@
+// 快照：固定创建时的日志末尾和 index，之后的写入、删除对快照都不可见
+//
+// 快照复制了 index 和所有段的文件句柄，不借用 ActionKV，可以交给别的线程慢慢遍历，
+// 写者照常追加；之后的压缩用新文件替换旧文件，快照持有的句柄仍然指向旧文件
+// 判断过期用的时间也固定在创建快照的时刻
+
+use std::collections::btree_map;
+use std::io;
+use std::ops::RangeBounds;
+use std::path::Path;
+
+use crate::backup::{self, BackupReport};
+use crate::log::{self, Segments};
+use crate::record::Record;
+use crate::scan::{self, Scan};
+use crate::namespace::{Keyspace, Namespace, Namespaces, DEFAULT};
+use crate::{ByteStr, ByteString, EncryptionKey, Expires, Index};
+
+#[derive(Debug)]
+pub struct Snapshot {
+    segments: Segments,
+    end: u64,
+    index: Index,
+    expires: Expires,
+    // 默认命名空间以外的命名空间
+    namespaces: Namespaces,
+    now: u64,
+    secret: Option<EncryptionKey>,
+    // 创建快照时最后分配的版本号
+    sequence: u64,
+}
+
+impl Snapshot {
+    pub(crate) fn new(
+        segments: Segments,
+        end: u64,
+        keyspace: Keyspace,
+        namespaces: Namespaces,
+        now: u64,
+        secret: Option<EncryptionKey>,
+        sequence: u64,
+    ) -> Self {
+        let Keyspace { index, expires } = keyspace;
+        Snapshot { segments, end, index, expires, namespaces, now, secret, sequence }
+    }
+
+    // 创建快照时的日志末尾，可以交给 get_as_of
+    pub fn end(&self) -> u64 {
+        self.end
+    }
+
+    pub(crate) fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    fn is_expired(&self, key: &ByteStr) -> bool {
+        self.expires.get(key).is_some_and(|&at| at <= self.now)
+    }
+
+    pub fn get(&self, key: &ByteStr) -> io::Result<Option<ByteString>> {
+        let position = match self.index.get(key) {
+            None => return Ok(None),
+            Some(position) => *position,
+        };
+        if self.is_expired(key) {
+            return Ok(None);
+        }
+        let record = log::read_at(&self.segments, position)?;
+        record.into_value(self.secret.as_ref()).map(Some)
+    }
+
+    pub fn contains_key(&self, key: &ByteStr) -> bool {
+        self.index.contains_key(key) && !self.is_expired(key)
+    }
+
+    pub fn scan<R: RangeBounds<ByteStr>>(&self, range: R) -> Scan<'_> {
+        self.scan_keys(self.index.range(range))
+    }
+
+    pub fn prefix(&self, prefix: &ByteStr) -> Scan<'_> {
+        self.scan_keys(self.index.range(scan::prefix_range(prefix)))
+    }
+
+    // 遍历快照中所有的 kv
+    pub fn iter(&self) -> Scan<'_> {
+        self.scan_keys(self.index.range::<ByteString, _>(..))
+    }
+
+    fn scan_keys<'a>(&'a self, keys: btree_map::Range<'a, ByteString, u64>) -> Scan<'a> {
+        Scan {
+            segments: &self.segments,
+            keys,
+            expires: &self.expires,
+            now: self.now,
+            secret: self.secret.as_ref(),
+        }
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &ByteString> {
+        self.index.keys().filter(move |key| !self.is_expired(key))
+    }
+
+    // 查找创建快照时已有的命名空间，见 namespace.rs
+    pub fn namespace(&self, name: &str) -> Option<Namespace<'_>> {
+        let id = self.namespaces.id(name)?;
+        Some(Namespace {
+            name: name.to_string(),
+            segments: &self.segments,
+            keyspace: self.namespaces.get(id),
+            now: self.now,
+            secret: self.secret.as_ref(),
+        })
+    }
+
+    // 把快照写成一个单文件存储，见 backup.rs
+    pub fn backup(&self, dest: &Path) -> io::Result<BackupReport> {
+        backup::backup(self, dest)
+    }
+
+    // 按命名空间和 key 的顺序读出所有命名空间中没有过期的记录，不解压也不解密
+    pub(crate) fn records(&self) -> impl Iterator<Item = io::Result<Record>> + '_ {
+        let spaces = self.namespaces.spaces().map(|(id, space)| (id, &space.index, &space.expires));
+        let spaces = [(DEFAULT, &self.index, &self.expires)].into_iter().chain(spaces);
+        let live = spaces.flat_map(move |(namespace, index, expires)| {
+            let live = index.iter().filter(move |(key, _)| {
+                expires.get(*key).is_none_or(|&at| at > self.now)
+            });
+            live.map(move |(key, &position)| (namespace, key, position))
+        });
+        live.map(move |(namespace, key, position)| {
+            let record = log::read_at(&self.segments, position)?;
+            if record.key != *key || record.namespace != namespace {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("record at offset {} does not belong to its key", position),
+                ));
+            }
+            Ok(record)
+        })
+    }
+}
@@ -0,0 +1,278 @@
+// 存储后端：日志的段和辅助数据（hint、replica）放在哪里
+//
+// Log 只通过 Storage 和 Segment 访问数据，不直接使用文件
+// FileStorage 是默认的后端，格式和以前完全相同：单文件存储只有 0 号段，就是 path 本身；
+// 分段存储的每个段是目录中的 NNNNNNNN.akv 文件
+// 另外有内存后端 MemoryStorage（见 memory.rs）和注入故障的 FaultyStorage（见 faulty.rs）
+//
+// 段只在末尾追加，读取用偏移，不依赖任何读写位置，多个线程可以同时读
+// 压缩先把新的段写成暂存段，commit 时一次替换，见 Storage::commit
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::io::prelude::*;
+#[cfg(unix)]
+use std::os::unix::fs::FileExt;
+#[cfg(windows)]
+use std::os::windows::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::ByteStr;
+
+// 日志中的一个段
+pub trait Segment: Send + Sync + fmt::Debug {
+    fn len(&self) -> io::Result<u64>;
+
+    fn is_empty(&self) -> io::Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    // 从 offset 开始读，返回读到的字节数，0 表示已经到末尾
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize>;
+
+    // 追加到末尾，返回写入的字节数，可能少于 buf 的长度
+    fn append(&self, buf: &ByteStr) -> io::Result<usize>;
+
+    // 把已经追加的数据落盘
+    fn sync(&self) -> io::Result<()>;
+
+    // 截断到 len，用于去掉写了一半的尾部
+    fn truncate(&self, len: u64) -> io::Result<()>;
+
+    // 段的身份：压缩换上的新段和同号的旧段不同，订阅靠它发现日志被替换
+    fn identity(&self) -> io::Result<(u64, u64)>;
+}
+
+pub trait Storage: Send + Sync + fmt::Debug {
+    // 已有的段号，从小到大
+    fn segment_ids(&self) -> io::Result<Vec<u32>>;
+
+    // 打开段，不存在时创建
+    fn open(&self, id: u32) -> io::Result<Arc<dyn Segment>>;
+
+    // 打开已有的段，不存在时返回 None
+    fn open_existing(&self, id: u32) -> io::Result<Option<Arc<dyn Segment>>>;
+
+    // 新建一个空的暂存段，commit 之前 open 看不到它
+    fn stage(&self, id: u32) -> io::Result<Arc<dyn Segment>>;
+
+    // 让暂存的段生效，替换同号的段，再按段号从小到大删除 removed 中的段
+    // 崩溃后留下的旧段总是一个后缀，不会让已删除的 key 复活
+    fn commit(&self, staged: &[u32], removed: &[u32]) -> io::Result<()>;
+
+    // 按名字读取辅助数据，不存在时返回 None
+    fn read_sidecar(&self, name: &str) -> io::Result<Option<Vec<u8>>>;
+
+    // 原子地写入辅助数据，读者要么看到旧的，要么看到新的
+    fn write_sidecar(&self, name: &str, data: &ByteStr) -> io::Result<()>;
+
+    // 删除辅助数据，不存在时什么也不做
+    fn remove_sidecar(&self, name: &str) -> io::Result<()>;
+
+    // 辅助数据的文件路径，不是文件后端时返回 None
+    fn sidecar_path(&self, _name: &str) -> Option<PathBuf> {
+        None
+    }
+}
+
+// 文件后端
+#[derive(Debug)]
+pub struct FileStorage {
+    path: PathBuf,
+    segmented: bool,
+}
+
+impl FileStorage {
+    // segmented 时 path 是一个目录，不存在时创建
+    pub fn open(path: &Path, segmented: bool) -> io::Result<Self> {
+        if segmented {
+            fs::create_dir_all(path)?;
+        }
+        Ok(FileStorage { path: path.to_path_buf(), segmented })
+    }
+
+    fn segment_path(&self, id: u32) -> PathBuf {
+        match self.segmented {
+            false => self.path.clone(),
+            true => self.path.join(format!("{:08}.akv", id)),
+        }
+    }
+
+    fn staged_path(&self, id: u32) -> PathBuf {
+        suffixed(&self.segment_path(id), "compact")
+    }
+
+    // 单文件存储放在数据文件旁边，例如 store.db -> store.db.hint
+    // 分段存储放在目录里，例如 store/actionkv.hint
+    fn path_of(&self, name: &str) -> PathBuf {
+        match self.segmented {
+            true => self.path.join(format!("actionkv.{}", name)),
+            false => suffixed(&self.path, name),
+        }
+    }
+}
+
+impl Storage for FileStorage {
+    fn segment_ids(&self) -> io::Result<Vec<u32>> {
+        if !self.segmented {
+            return Ok(if self.path.exists() { vec![0] } else { Vec::new() });
+        }
+        let mut ids = Vec::new();
+        for entry in fs::read_dir(&self.path)? {
+            let name = entry?.file_name();
+            let id = name
+                .to_str()
+                .and_then(|name| name.strip_suffix(".akv"))
+                .and_then(|id| id.parse::<u32>().ok());
+            if let Some(id) = id {
+                ids.push(id);
+            }
+        }
+        ids.sort_unstable();
+        Ok(ids)
+    }
+
+    fn open(&self, id: u32) -> io::Result<Arc<dyn Segment>> {
+        FileSegment::open(self.segment_path(id)).map(|segment| Arc::new(segment) as _)
+    }
+
+    fn open_existing(&self, id: u32) -> io::Result<Option<Arc<dyn Segment>>> {
+        if !self.segmented && id != 0 {
+            return Ok(None);
+        }
+        let path = self.segment_path(id);
+        match File::open(&path) {
+            Ok(file) => Ok(Some(Arc::new(FileSegment { file, path }))),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn stage(&self, id: u32) -> io::Result<Arc<dyn Segment>> {
+        let path = self.staged_path(id);
+        // 上次压缩中途失败留下的暂存文件
+        remove_file(&path)?;
+        FileSegment::open(path).map(|segment| Arc::new(segment) as _)
+    }
+
+    fn commit(&self, staged: &[u32], removed: &[u32]) -> io::Result<()> {
+        for &id in staged {
+            fs::rename(self.staged_path(id), self.segment_path(id))?;
+        }
+        for &id in removed {
+            fs::remove_file(self.segment_path(id))?;
+        }
+        Ok(())
+    }
+
+    fn read_sidecar(&self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(self.path_of(name)) {
+            Ok(data) => Ok(Some(data)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn write_sidecar(&self, name: &str, data: &ByteStr) -> io::Result<()> {
+        // 先写临时文件再 rename，不会读到写了一半的数据
+        let path = self.path_of(name);
+        let tmp_path = suffixed(&path, "tmp");
+        let mut f = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp_path)?;
+        f.write_all(data)?;
+        f.sync_all()?;
+        fs::rename(&tmp_path, path)
+    }
+
+    fn remove_sidecar(&self, name: &str) -> io::Result<()> {
+        remove_file(&self.path_of(name))
+    }
+
+    fn sidecar_path(&self, name: &str) -> Option<PathBuf> {
+        Some(self.path_of(name))
+    }
+}
+
+#[derive(Debug)]
+struct FileSegment {
+    file: File,
+    // 截断时需要以写方式重新打开
+    path: PathBuf,
+}
+
+impl FileSegment {
+    fn open(path: PathBuf) -> io::Result<Self> {
+        // append(true) 已经隐含了 write(true)
+        let file = OpenOptions::new()
+            .read(true)
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        Ok(FileSegment { file, path })
+    }
+}
+
+impl Segment for FileSegment {
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    // 用 pread 读取，不改变文件的读写位置
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        #[cfg(unix)]
+        let n = self.file.read_at(buf, offset)?;
+        // windows 的 seek_read 会移动文件位置，但追加写总是写到文件末尾，不受影响
+        #[cfg(windows)]
+        let n = self.file.seek_read(buf, offset)?;
+        Ok(n)
+    }
+
+    // 以追加方式打开，写入总在文件末尾
+    fn append(&self, buf: &ByteStr) -> io::Result<usize> {
+        (&self.file).write(buf)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.file.sync_data()
+    }
+
+    fn truncate(&self, len: u64) -> io::Result<()> {
+        // 只读打开的段（例如订阅打开的）也可以截断
+        let f = OpenOptions::new().write(true).open(&self.path)?;
+        f.set_len(len)?;
+        f.sync_all()
+    }
+
+    #[cfg(unix)]
+    fn identity(&self) -> io::Result<(u64, u64)> {
+        use std::os::unix::fs::MetadataExt;
+        let meta = self.file.metadata()?;
+        Ok((meta.dev(), meta.ino()))
+    }
+
+    // 其他平台只能靠长度判断
+    #[cfg(not(unix))]
+    fn identity(&self) -> io::Result<(u64, u64)> {
+        Ok((0, 0))
+    }
+}
+
+// store.db -> store.db.tmp
+pub(crate) fn suffixed(path: &Path, extension: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".");
+    name.push(extension);
+    PathBuf::from(name)
+}
+
+fn remove_file(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,174 @@
+// 变更订阅（CDC）：按日志顺序读出已提交的 insert、update 和 delete
+//
+// 订阅从给定的位置开始读，追上日志末尾时 next 返回 None，之后有新的写入时可以继续调用 next
+// 批次只在读到 commit 之后才交付，未提交的批次不会出现
+// position 是恢复订阅用的位置，总是落在完整交付的变更之后、批次之外，
+// 保存它，下次从这里订阅不会漏掉变更；在一个批次交付到一半时保存，恢复后会重复收到这个批次
+//
+// 订阅只读取存储后端，不借用 ActionKV，可以在别的线程中跟随写者；文件后端也可以在别的进程中跟随
+// 压缩会替换日志的段并改变所有位置，之后订阅会返回错误，需要从 0 重新订阅
+// 过期的记录照常交付，变更中带有过期时间
+// 所有命名空间的变更都会交付，namespace 是命名空间 id，默认命名空间为 0；命名空间目录的变更不交付
+
+use std::collections::VecDeque;
+use std::io;
+use std::sync::Arc;
+
+use crate::batch::Pending;
+use crate::log::{self, Segments};
+use crate::namespace::CATALOG;
+use crate::record::Record;
+use crate::storage::Storage;
+use crate::{make_position, offset_of, segment_of, ByteString, EncryptionKey};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    // 记录的位置
+    pub position: u64,
+    pub key: ByteString,
+    // None 表示删除
+    pub value: Option<ByteString>,
+    pub expires: Option<u64>,
+    pub version: Option<u64>,
+    pub namespace: u32,
+}
+
+#[derive(Debug)]
+pub struct Subscription {
+    storage: Arc<dyn Storage>,
+    segments: Segments,
+    // 下一条要读的记录
+    cursor: u64,
+    // 恢复订阅的位置
+    resume: u64,
+    pending: Pending,
+    // 已经提交、还没有交付的记录
+    ready: VecDeque<(u64, Record)>,
+    secret: Option<EncryptionKey>,
+}
+
+impl Subscription {
+    pub(crate) fn new(
+        storage: Arc<dyn Storage>,
+        segments: Segments,
+        from: u64,
+        secret: Option<EncryptionKey>,
+    ) -> Self {
+        Subscription {
+            storage,
+            segments,
+            cursor: from,
+            resume: from,
+            pending: Pending::default(),
+            ready: VecDeque::new(),
+            secret,
+        }
+    }
+
+    // 恢复订阅用的位置，见文件开头
+    pub fn position(&self) -> u64 {
+        self.resume
+    }
+
+    // 读一条记录，返回 false 表示已经追上日志末尾
+    fn read_next(&mut self) -> io::Result<bool> {
+        let id = segment_of(self.cursor);
+        if !self.segments.contains_key(&id) {
+            let segment = self.storage.open_existing(id)?.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, format!("segment {} not found", id))
+            })?;
+            self.segments.insert(id, segment);
+        }
+        let len = self.segments[&id].len()?;
+        if offset_of(self.cursor) >= len {
+            // 压缩后的新段号接在旧段之后，换段之前先确认这一段没有被替换
+            self.check_replaced(id)?;
+            // 分段存储写满一段后换到下一段
+            if offset_of(self.cursor) == len && self.storage.open_existing(id + 1)?.is_some() {
+                self.cursor = make_position(id + 1, 0);
+                self.mark_resume();
+                return Ok(true);
+            }
+            return Ok(false);
+        }
+
+        let record = match log::read_at(&self.segments, self.cursor) {
+            Ok(record) => record,
+            // 写者正在追加，记录还不完整
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                self.check_replaced(id)?;
+                return Ok(false);
+            }
+            Err(err) => return Err(err),
+        };
+        let position = self.cursor;
+        self.cursor += record.len();
+        self.ready.extend(self.pending.push(position, record)?);
+        self.mark_resume();
+        Ok(true)
+    }
+
+    fn mark_resume(&mut self) {
+        if self.ready.is_empty() && !self.pending.is_open() {
+            self.resume = self.cursor;
+        }
+    }
+
+    // 追上末尾时检查段是否已经被压缩替换或截断
+    fn check_replaced(&self, id: u32) -> io::Result<()> {
+        let current = self.storage.open_existing(id)?.ok_or_else(replaced)?;
+        let ours = &self.segments[&id];
+        if current.len()? < offset_of(self.cursor) || ours.identity()? != current.identity()? {
+            return Err(replaced());
+        }
+        Ok(())
+    }
+
+    // 下一条已提交的记录，不解码，复制日志时原样转发
+    pub(crate) fn next_record(&mut self) -> Option<io::Result<(u64, Record)>> {
+        loop {
+            if let Some(next) = self.ready.pop_front() {
+                self.mark_resume();
+                return Some(Ok(next));
+            }
+            match self.read_next() {
+                Ok(true) => continue,
+                Ok(false) => return None,
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+
+    fn change(&self, position: u64, record: Record) -> io::Result<Change> {
+        let namespace = record.namespace;
+        let kv = record.into_kv(self.secret.as_ref())?;
+        Ok(Change {
+            position,
+            key: kv.key,
+            value: (!kv.tombstone).then_some(kv.value),
+            expires: kv.expires,
+            version: kv.version,
+            namespace,
+        })
+    }
+}
+
+impl Iterator for Subscription {
+    type Item = io::Result<Change>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (position, record) = match self.next_record()? {
+                Ok(next) => next,
+                Err(err) => return Some(Err(err)),
+            };
+            if record.namespace != CATALOG {
+                return Some(self.change(position, record));
+            }
+        }
+    }
+}
+
+fn replaced() -> io::Error {
+    io::Error::other("log was compacted or truncated, subscribe again from offset 0")
+}
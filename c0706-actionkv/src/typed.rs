@@ -0,0 +1,201 @@
+// 带类型的存储：key 和 value 是任意可以用 serde 序列化的类型，读写时自动编码、解码
+//
+// 编码可以选 bincode、JSON 或 CBOR，同一个存储应该始终使用同一种编码，存储本身不记录用的是哪种
+// 解码失败（编码不一致、类型变了、数据不完整）返回 io::ErrorKind::InvalidData 错误，
+// 内部错误是 DecodeError，不会 panic；多余的尾部字节也算解码失败
+// 遍历按编码后 key 的字节顺序，不一定是 K 本身的顺序，例如 bincode 的整数是小端的
+
+use std::fmt;
+use std::io;
+use std::marker::PhantomData;
+use std::ops::RangeFull;
+use std::str::FromStr;
+use std::time::Duration;
+
+use bincode::Options as _;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::{ActionKV, ByteStr, ByteString};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    // 紧凑，速度快，不能跨语言读取
+    #[default]
+    Bincode,
+    Json,
+    Cbor,
+}
+
+impl FromStr for Encoding {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bincode" => Ok(Encoding::Bincode),
+            "json" => Ok(Encoding::Json),
+            "cbor" => Ok(Encoding::Cbor),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown encoding {:?}, expected bincode, json or cbor", s),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Encoding::Bincode => "bincode",
+            Encoding::Json => "json",
+            Encoding::Cbor => "cbor",
+        };
+        f.write_str(name)
+    }
+}
+
+// 无法解码的 key 或 value，作为 io::ErrorKind::InvalidData 错误的内部错误返回
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeError {
+    pub encoding: Encoding,
+    // 原始的 key，解码失败的是 key 本身时也是它
+    pub key: ByteString,
+    pub message: String,
+}
+
+impl DecodeError {
+    // 从 io::Error 中取出 DecodeError
+    pub fn from_io_error(err: &io::Error) -> Option<&DecodeError> {
+        err.get_ref()?.downcast_ref::<DecodeError>()
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unable to decode {} data for key {:?}: {}",
+            self.encoding, self.key, self.message
+        )
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+// 包装一个已经 load 过的 ActionKV，只使用默认命名空间
+#[derive(Debug)]
+pub struct TypedStore<K, V> {
+    store: ActionKV,
+    encoding: Encoding,
+    types: PhantomData<fn() -> (K, V)>,
+}
+
+impl<K, V> TypedStore<K, V>
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    pub fn new(store: ActionKV, encoding: Encoding) -> Self {
+        TypedStore { store, encoding, types: PhantomData }
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    pub fn get(&self, key: &K) -> io::Result<Option<V>> {
+        let raw = encode(self.encoding, key)?;
+        match self.store.get(&raw)? {
+            None => Ok(None),
+            Some(value) => decode(self.encoding, &raw, &value).map(Some),
+        }
+    }
+
+    pub fn contains_key(&self, key: &K) -> io::Result<bool> {
+        Ok(self.store.contains_key(&encode(self.encoding, key)?))
+    }
+
+    pub fn insert(&mut self, key: &K, value: &V) -> io::Result<()> {
+        let (key, value) = (encode(self.encoding, key)?, encode(self.encoding, value)?);
+        self.store.insert(&key, &value)
+    }
+
+    pub fn insert_with_ttl(&mut self, key: &K, value: &V, ttl: Duration) -> io::Result<()> {
+        let (key, value) = (encode(self.encoding, key)?, encode(self.encoding, value)?);
+        self.store.insert_with_ttl(&key, &value, ttl)
+    }
+
+    pub fn delete(&mut self, key: &K) -> io::Result<()> {
+        self.store.delete(&encode(self.encoding, key)?)
+    }
+
+    // 按编码后 key 的字节顺序遍历所有没有过期的 kv，见文件开头
+    pub fn iter(&self) -> impl Iterator<Item = io::Result<(K, V)>> + '_ {
+        let encoding = self.encoding;
+        self.store.scan::<RangeFull>(..).map(move |kv| {
+            let (key, value) = kv?;
+            Ok((decode(encoding, &key, &key)?, decode(encoding, &key, &value)?))
+        })
+    }
+
+    // 没有过期的 key，只解码 key，不读 value
+    pub fn keys(&self) -> impl Iterator<Item = io::Result<K>> + '_ {
+        let encoding = self.encoding;
+        self.store.keys().map(move |key| decode(encoding, key, key))
+    }
+
+    // 原始的 ActionKV，可以用来 compact、close、订阅等
+    pub fn inner(&self) -> &ActionKV {
+        &self.store
+    }
+
+    pub fn inner_mut(&mut self) -> &mut ActionKV {
+        &mut self.store
+    }
+
+    pub fn into_inner(self) -> ActionKV {
+        self.store
+    }
+}
+
+// bincode 使用定长整数，和 bincode::serialize 的格式相同，但解码时不允许多余的字节
+fn bincode_options() -> impl bincode::Options {
+    bincode::DefaultOptions::new().with_fixint_encoding()
+}
+
+fn encode<T: Serialize>(encoding: Encoding, value: &T) -> io::Result<ByteString> {
+    let encoded = match encoding {
+        Encoding::Bincode => bincode_options().serialize(value).map_err(|err| err.to_string()),
+        Encoding::Json => serde_json::to_vec(value).map_err(|err| err.to_string()),
+        Encoding::Cbor => {
+            let mut buf = ByteString::new();
+            ciborium::into_writer(value, &mut buf).map(|_| buf).map_err(|err| err.to_string())
+        }
+    };
+    encoded.map_err(|message| {
+        let message = format!("unable to encode {}: {}", encoding, message);
+        io::Error::new(io::ErrorKind::InvalidInput, message)
+    })
+}
+
+// key 是原始的 key，只用于错误信息
+fn decode<T>(encoding: Encoding, key: &ByteStr, bytes: &ByteStr) -> io::Result<T>
+where
+    T: DeserializeOwned,
+{
+    let decoded = match encoding {
+        Encoding::Bincode => bincode_options().deserialize(bytes).map_err(|err| err.to_string()),
+        Encoding::Json => serde_json::from_slice(bytes).map_err(|err| err.to_string()),
+        Encoding::Cbor => {
+            let mut rest = bytes;
+            match ciborium::from_reader(&mut rest) {
+                Ok(_) if !rest.is_empty() => Err(format!("{} trailing bytes", rest.len())),
+                result => result.map_err(|err| err.to_string()),
+            }
+        }
+    };
+    decoded.map_err(|message| {
+        let err = DecodeError { encoding, key: key.to_vec(), message };
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    })
+}
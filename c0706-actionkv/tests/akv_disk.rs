@@ -0,0 +1,117 @@
+// 运行 akv_disk 可执行文件，测试 checkpoint 和旧版本留下的 +index 记录
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::{env, fs, process};
+
+use libactionkv::{ActionKV, Recovery, CRC};
+
+fn temp_path(name: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("actionkv-akv-disk-{}-{}", process::id(), name));
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_file(checkpoint_path(&path));
+    path
+}
+
+// 数据文件旁边的 checkpoint：FILE.hint
+fn checkpoint_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".hint");
+    PathBuf::from(name)
+}
+
+// 运行 akv_disk FILE args...，把 input 写到标准输入，返回标准输出
+fn akv_disk(path: &Path, args: &[&str], input: &str) -> String {
+    let output = run(path, args, input);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn run(path: &Path, args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_akv_disk"))
+        .arg(path)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn checkpoint_is_reused_after_many_writes() {
+    let path = temp_path("checkpoint");
+    // 一次导入超过 CHECKPOINT_EVERY 条记录，结束时写入 checkpoint
+    let input: String =
+        (0..1500).map(|i| format!("{{\"key\":\"k{}\",\"value\":\"{}\"}}\n", i, i)).collect();
+    akv_disk(&path, &["import"], &input);
+    let checkpoint = fs::read(checkpoint_path(&path)).unwrap();
+
+    // 之后的少量写入不重写 checkpoint，重新打开时只扫描 checkpoint 之后的记录
+    akv_disk(&path, &["insert", "k1500", "new"], "");
+    akv_disk(&path, &["delete", "k0"], "");
+    assert_eq!(fs::read(checkpoint_path(&path)).unwrap(), checkpoint);
+    assert_eq!(akv_disk(&path, &["get", "k1500"], ""), format!("{:?}\n", b"new".to_vec()));
+    assert_eq!(akv_disk(&path, &["get", "k0"], ""), "");
+    assert_eq!(akv_disk(&path, &["list", "k"], "").lines().count(), 1500);
+
+    let mut store = ActionKV::open(&path).unwrap();
+    let report = store.load_with(Recovery::Strict).unwrap();
+    assert_eq!((report.hinted, report.records), (true, 2));
+    assert_eq!(store.get(b"k1499").unwrap(), Some(b"1499".to_vec()));
+    fs::remove_file(checkpoint_path(&path)).unwrap();
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn legacy_index_record_is_removed() {
+    // 旧版本写入的文件：a、b 和序列化了整个 index 的 +index，记录没有标志
+    let path = temp_path("legacy");
+    let index: HashMap<Vec<u8>, u64> = HashMap::from([(b"a".to_vec(), 0), (b"b".to_vec(), 14)]);
+    let index = bincode::serialize(&index).unwrap();
+    let mut buf = Vec::new();
+    for (key, value) in [(&b"a"[..], &b"1"[..]), (b"b", b"2"), (b"+index", &index)] {
+        let data = [key, value].concat();
+        buf.extend_from_slice(&CRC.checksum(&data).to_le_bytes());
+        buf.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&data);
+    }
+    fs::write(&path, buf).unwrap();
+
+    assert_eq!(akv_disk(&path, &["list"], ""), "[97] [49]\n[98] [50]\n");
+    assert_eq!(akv_disk(&path, &["get", "+index"], ""), "");
+
+    // 用户自己写入的 +index 是普通的 key
+    akv_disk(&path, &["insert", "+index", "mine"], "");
+    assert_eq!(akv_disk(&path, &["get", "+index"], ""), format!("{:?}\n", b"mine".to_vec()));
+    let _ = fs::remove_file(checkpoint_path(&path));
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn skip_recovery_is_read_only() {
+    let path = temp_path("skip");
+    akv_disk(&path, &["insert", "a", "1"], "");
+    akv_disk(&path, &["insert", "b", "2"], "");
+    let bytes = fs::read(&path).unwrap();
+    fs::write(&path, &bytes[..bytes.len() - 5]).unwrap();
+
+    // 读取可以跳过残缺的尾部，写入被拒绝，文件不变
+    assert_eq!(akv_disk(&path, &["--recover=skip", "list"], ""), "[97] [49]\n");
+    for args in [&["insert", "c", "3"][..], &["update", "a", "3"], &["delete", "a"], &["import"]] {
+        let args = [&["--recover=skip"], args].concat();
+        assert!(!run(&path, &args, "").status.success(), "{:?}", args);
+    }
+    assert_eq!(fs::read(&path).unwrap(), &bytes[..bytes.len() - 5]);
+
+    // truncate 截断之后写入，严格加载能读到新记录
+    akv_disk(&path, &["--recover=truncate", "insert", "c", "3"], "");
+    assert_eq!(akv_disk(&path, &["list"], ""), "[97] [49]\n[99] [51]\n");
+    let _ = fs::remove_file(checkpoint_path(&path));
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,67 @@
+// AsyncActionKV 的测试，需要 cargo test --features async
+
+#![cfg(feature = "async")]
+
+use std::path::{Path, PathBuf};
+use std::{env, fs, io, process};
+
+use libactionkv::{ActionKV, AsyncActionKV, Conflict, WriteBatch};
+
+fn temp_path(name: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("actionkv-async-{}-{}", process::id(), name));
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_file(hint_path(&path));
+    path
+}
+
+// 存储旁边的 hint 文件：FILE.hint
+fn hint_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".hint");
+    PathBuf::from(name)
+}
+
+#[tokio::test]
+async fn matches_the_sync_api() {
+    let path = temp_path("basic");
+    let store = AsyncActionKV::open(&path).await.unwrap();
+    store.insert(b"a", b"1").await.unwrap();
+    store.insert(b"b", b"2").await.unwrap();
+    store.insert(b"c", b"3").await.unwrap();
+    store.delete(b"b").await.unwrap();
+    let mut batch = WriteBatch::new();
+    batch.insert(b"d", b"4").delete(b"a");
+    store.write_batch(batch).await.unwrap();
+
+    assert_eq!(store.get(b"a").await.unwrap(), None);
+    assert_eq!(store.get(b"c").await.unwrap(), Some(b"3".to_vec()));
+    assert!(store.contains_key(b"d").await.unwrap());
+    let all = store.scan(b"c".to_vec()..).await.unwrap();
+    assert_eq!(all, vec![(b"c".to_vec(), b"3".to_vec()), (b"d".to_vec(), b"4".to_vec())]);
+    assert_eq!(store.prefix(b"d").await.unwrap().len(), 1);
+
+    let (_, version) = store.get_with_version(b"c").await.unwrap().unwrap();
+    let err = store.update_if_version(b"c", version + 1, b"x").await.unwrap_err();
+    assert!(Conflict::from_io_error(&err).is_some());
+    store.update_if_version(b"c", version, b"x").await.unwrap();
+
+    // close 之后其他句柄也不能再用
+    let other = store.clone();
+    store.close().await.unwrap();
+    assert_eq!(other.get(b"c").await.unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+    assert!(hint_path(&path).exists());
+
+    let mut reopened = ActionKV::open(&path).unwrap();
+    reopened.load().unwrap();
+    assert_eq!(reopened.get(b"c").unwrap(), Some(b"x".to_vec()));
+    assert_eq!(reopened.keys().count(), 2);
+    fs::remove_file(&path).unwrap();
+    fs::remove_file(hint_path(&path)).unwrap();
+}
+
+#[tokio::test]
+async fn open_errors_are_returned() {
+    let path = temp_path("missing-dir").join("store");
+    let err = AsyncActionKV::open(&path).await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+}
@@ -0,0 +1,225 @@
+// 在 localhost 上运行主库和从库，测试日志复制
+
+use std::io;
+use std::io::prelude::*;
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{env, fs, process, thread};
+
+use libactionkv::{serve_replication, ActionKV, Follower, SharedActionKV, WriteBatch};
+
+fn temp_path(name: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("actionkv-replication-{}-{}", process::id(), name));
+    remove(&path);
+    path
+}
+
+fn remove(path: &Path) {
+    let _ = fs::remove_file(path);
+    for suffix in ["hint", "replica"] {
+        let _ = fs::remove_file(sidecar(path, suffix));
+    }
+}
+
+// 存储旁边的辅助文件：FILE.hint、FILE.replica
+fn sidecar(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+fn open(path: &Path) -> ActionKV {
+    let mut store = ActionKV::open(path).unwrap();
+    store.load().unwrap();
+    store
+}
+
+// 启动主库的复制服务，返回地址
+fn start_leader(store: &SharedActionKV) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let store = store.clone();
+    thread::spawn(move || serve_replication(listener, store));
+    addr
+}
+
+// 等待从库追上主库的日志末尾
+fn wait_for(follower: &Follower, leader: &SharedActionKV) {
+    let end = leader.write(|kv| kv.seek_to_end()).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while follower.offset() != end {
+        assert!(Instant::now() < deadline, "follower stuck at {}", follower.offset());
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn records(store: &ActionKV) -> u64 {
+    store.fsck().unwrap().records
+}
+
+// 转发连接的代理，cut 断开当前所有连接，之后的新连接照常转发
+#[derive(Clone, Default)]
+struct Proxy {
+    connections: Arc<Mutex<Vec<TcpStream>>>,
+}
+
+impl Proxy {
+    fn start(target: SocketAddr) -> (Proxy, SocketAddr) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let proxy = Proxy::default();
+        let connections = proxy.connections.clone();
+        thread::spawn(move || {
+            for client in listener.incoming() {
+                let client = client.unwrap();
+                let server = TcpStream::connect(target).unwrap();
+                let mut list = connections.lock().unwrap();
+                list.push(client.try_clone().unwrap());
+                list.push(server.try_clone().unwrap());
+                pipe(client.try_clone().unwrap(), server.try_clone().unwrap());
+                pipe(server, client);
+            }
+        });
+        (proxy, addr)
+    }
+
+    fn cut(&self) {
+        for stream in self.connections.lock().unwrap().drain(..) {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+fn pipe(mut from: TcpStream, mut to: TcpStream) {
+    thread::spawn(move || {
+        let _ = io::copy(&mut from, &mut to);
+        let _ = to.shutdown(Shutdown::Both);
+    });
+}
+
+#[test]
+fn followers_apply_committed_writes() {
+    let leader_path = temp_path("leader");
+    let leader = SharedActionKV::open(&leader_path).unwrap();
+    leader.insert(b"a", b"1").unwrap();
+    leader.insert(b"b", b"2").unwrap();
+    let addr = start_leader(&leader);
+
+    let paths = [temp_path("follower-1"), temp_path("follower-2")];
+    let followers: Vec<Follower> =
+        paths.iter().map(|path| Follower::start(open(path), addr).unwrap()).collect();
+
+    leader.delete(b"a").unwrap();
+    let mut batch = WriteBatch::new();
+    batch.insert(b"c", b"3").insert(b"d", b"4").delete(b"b");
+    leader.write(|kv| kv.write_batch(&batch)).unwrap();
+    leader.insert_with_ttl(b"e", b"5", Duration::from_secs(3600)).unwrap();
+
+    let expected = leader.scan(..).unwrap();
+    for follower in &followers {
+        wait_for(follower, &leader);
+        let scanned = follower.read(|kv| kv.scan(..).collect::<io::Result<Vec<_>>>());
+        assert_eq!(scanned.unwrap(), expected);
+        assert_eq!(
+            follower.read(|kv| kv.version(b"c")).unwrap(),
+            leader.version(b"c").unwrap()
+        );
+        assert!(follower.read(|kv| kv.expiry(b"e")).is_some());
+        assert_eq!(follower.read(records), leader.read(records));
+        assert_eq!(follower.last_error(), None);
+    }
+
+    // 提升之后可以写入，和主库分叉，不再跟随
+    let mut followers = followers.into_iter();
+    let promoted = followers.next().unwrap().promote().unwrap();
+    promoted.insert(b"f", b"6").unwrap();
+    leader.insert(b"g", b"7").unwrap();
+    let follower = followers.next().unwrap();
+    wait_for(&follower, &leader);
+    assert_eq!(follower.get(b"g").unwrap(), Some(b"7".to_vec()));
+    assert_eq!(follower.get(b"f").unwrap(), None);
+    assert_eq!(leader.get(b"f").unwrap(), None);
+    assert_eq!(promoted.get(b"g").unwrap(), None);
+    assert!(!sidecar(&paths[0], "replica").exists());
+    assert!(sidecar(&paths[1], "replica").exists());
+
+    drop(follower);
+    drop(promoted);
+    remove(&leader_path);
+    for path in &paths {
+        remove(path);
+    }
+}
+
+#[test]
+fn followers_resume_after_reconnect_and_restart() {
+    let leader_path = temp_path("resume-leader");
+    let leader = SharedActionKV::open(&leader_path).unwrap();
+    let (proxy, addr) = Proxy::start(start_leader(&leader));
+
+    let path = temp_path("resume-follower");
+    let follower = Follower::start(open(&path), addr).unwrap();
+    for i in 0..10 {
+        leader.insert(format!("k{}", i).as_bytes(), b"v").unwrap();
+    }
+    wait_for(&follower, &leader);
+
+    // 断开期间的写入在重连后补上，之前的记录不会重复写入
+    proxy.cut();
+    for i in 10..20 {
+        leader.insert(format!("k{}", i).as_bytes(), b"v").unwrap();
+    }
+    wait_for(&follower, &leader);
+    assert_eq!(follower.read(|kv| kv.keys().count()), 20);
+    assert_eq!(follower.read(records), leader.read(records));
+
+    // 重启从库：从 replica 文件记下的位置继续
+    let offset = follower.offset();
+    drop(follower);
+    leader.delete(b"k0").unwrap();
+    let follower = Follower::start(open(&path), addr).unwrap();
+    assert_eq!(follower.offset(), offset);
+    wait_for(&follower, &leader);
+    assert_eq!(follower.get(b"k0").unwrap(), None);
+    assert_eq!(follower.read(records), leader.read(records));
+
+    drop(follower);
+    remove(&leader_path);
+    remove(&path);
+}
+
+#[test]
+fn oversized_frames_are_rejected() {
+    // 假的主库：读完握手后发送一个声明长度为 4 GiB 的 R 帧
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut handshake = [0; 12];
+            stream.read_exact(&mut handshake).unwrap();
+            let mut frame = vec![b'R'];
+            frame.extend_from_slice(&0u64.to_le_bytes());
+            frame.extend_from_slice(&u32::MAX.to_le_bytes());
+            let _ = stream.write_all(&frame);
+        }
+    });
+
+    let path = temp_path("oversized");
+    let follower = Follower::start(open(&path), addr).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let error = loop {
+        if let Some(error) = follower.last_error() {
+            break error;
+        }
+        assert!(Instant::now() < deadline, "follower never failed");
+        thread::sleep(Duration::from_millis(10));
+    };
+    assert!(error.contains("too large"), "{}", error);
+    assert_eq!(follower.offset(), 0);
+    drop(follower);
+    remove(&path);
+}
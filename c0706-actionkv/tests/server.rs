@@ -0,0 +1,241 @@
+// 在 localhost 上启动 akv 服务，用最简单的 RESP 客户端测试
+
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use std::{env, fs, process, thread};
+
+use libactionkv::{ActionKV, Follower, SharedActionKV};
+
+#[derive(Debug, PartialEq, Eq)]
+enum Value {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Vec<Value>),
+}
+
+fn bulk(data: &[u8]) -> Value {
+    Value::Bulk(Some(data.to_vec()))
+}
+
+struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Client {
+    fn connect(addr: SocketAddr) -> Client {
+        let writer = TcpStream::connect(addr).unwrap();
+        let reader = BufReader::new(writer.try_clone().unwrap());
+        Client { reader, writer }
+    }
+
+    fn send(&mut self, args: &[&[u8]]) {
+        let mut buf = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in args {
+            buf.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+            buf.extend_from_slice(arg);
+            buf.extend_from_slice(b"\r\n");
+        }
+        self.writer.write_all(&buf).unwrap();
+    }
+
+    fn command(&mut self, args: &[&[u8]]) -> Value {
+        self.send(args);
+        self.read()
+    }
+
+    fn read(&mut self) -> Value {
+        let mut line = String::new();
+        self.reader.read_line(&mut line).unwrap();
+        let line = line.trim_end_matches("\r\n");
+        let (kind, rest) = line.split_at(1);
+        match kind {
+            "+" => Value::Simple(rest.to_string()),
+            "-" => Value::Error(rest.to_string()),
+            ":" => Value::Integer(rest.parse().unwrap()),
+            "$" => {
+                let len: i64 = rest.parse().unwrap();
+                if len < 0 {
+                    return Value::Bulk(None);
+                }
+                let mut data = vec![0; len as usize + 2];
+                self.reader.read_exact(&mut data).unwrap();
+                data.truncate(len as usize);
+                Value::Bulk(Some(data))
+            }
+            "*" => {
+                let len: usize = rest.parse().unwrap();
+                Value::Array((0..len).map(|_| self.read()).collect())
+            }
+            _ => panic!("unexpected reply {:?}", line),
+        }
+    }
+}
+
+fn start(name: &str) -> (SocketAddr, PathBuf) {
+    let path = env::temp_dir().join(format!("actionkv-server-{}-{}", process::id(), name));
+    let _ = fs::remove_file(&path);
+    let store = SharedActionKV::open(&path).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || libactionkv::serve(listener, store));
+    (addr, path)
+}
+
+#[test]
+fn ping() {
+    let (addr, path) = start("ping");
+    let mut client = Client::connect(addr);
+    assert_eq!(client.command(&[b"PING"]), Value::Simple("PONG".to_string()));
+    assert_eq!(client.command(&[b"ping", b"hello"]), bulk(b"hello"));
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn set_get_del_exists() {
+    let (addr, path) = start("commands");
+    let mut client = Client::connect(addr);
+    assert_eq!(client.command(&[b"SET", b"a", b"1"]), Value::Simple("OK".to_string()));
+    // 二进制数据，包含 \r\n
+    assert_eq!(client.command(&[b"SET", b"b", b"x\r\ny\0"]), Value::Simple("OK".to_string()));
+    assert_eq!(client.command(&[b"GET", b"a"]), bulk(b"1"));
+    assert_eq!(client.command(&[b"GET", b"b"]), bulk(b"x\r\ny\0"));
+    assert_eq!(client.command(&[b"GET", b"missing"]), Value::Bulk(None));
+    assert_eq!(client.command(&[b"EXISTS", b"a", b"a", b"missing"]), Value::Integer(2));
+    assert_eq!(client.command(&[b"DEL", b"a", b"missing"]), Value::Integer(1));
+    assert_eq!(client.command(&[b"GET", b"a"]), Value::Bulk(None));
+    assert_eq!(client.command(&[b"EXISTS", b"a"]), Value::Integer(0));
+    // 换算成毫秒后超出 u64 的过期时间当作永不过期
+    let set = [&b"SET"[..], b"c", b"3", b"EX", b"18446744073709551615"];
+    assert_eq!(client.command(&set), Value::Simple("OK".to_string()));
+    assert_eq!(client.command(&[b"GET", b"c"]), bulk(b"3"));
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn writes_are_visible_to_other_clients() {
+    let (addr, path) = start("clients");
+    let mut first = Client::connect(addr);
+    let mut second = Client::connect(addr);
+    first.command(&[b"SET", b"shared", b"1"]);
+    assert_eq!(second.command(&[b"GET", b"shared"]), bulk(b"1"));
+    second.command(&[b"DEL", b"shared"]);
+    assert_eq!(first.command(&[b"GET", b"shared"]), Value::Bulk(None));
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn scan_visits_every_key() {
+    let (addr, path) = start("scan");
+    let mut client = Client::connect(addr);
+    for i in 0..25 {
+        client.command(&[b"SET", format!("k{:02}", i).as_bytes(), b"v"]);
+    }
+
+    let mut keys = Vec::new();
+    let mut cursor = b"0".to_vec();
+    loop {
+        let reply = client.command(&[b"SCAN", &cursor, b"COUNT", b"7"]);
+        let Value::Array(mut reply) = reply else { panic!("unexpected reply {:?}", reply) };
+        let Value::Array(batch) = reply.pop().unwrap() else { panic!("keys are not an array") };
+        let Value::Bulk(Some(next)) = reply.pop().unwrap() else { panic!("invalid cursor") };
+        keys.extend(batch);
+        cursor = next;
+        if cursor == b"0" {
+            break;
+        }
+    }
+    let expected: Vec<Value> = (0..25).map(|i| bulk(format!("k{:02}", i).as_bytes())).collect();
+    assert_eq!(keys, expected);
+
+    let reply = client.command(&[b"SCAN", b"0", b"MATCH", b"k1?", b"COUNT", b"100"]);
+    let expected: Vec<Value> = (10..20).map(|i| bulk(format!("k{}", i).as_bytes())).collect();
+    assert_eq!(reply, Value::Array(vec![bulk(b"0"), Value::Array(expected)]));
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn pipelined_and_inline_commands() {
+    let (addr, path) = start("pipeline");
+    let mut client = Client::connect(addr);
+    client.send(&[b"SET", b"a", b"1"]);
+    client.send(&[b"GET", b"a"]);
+    client.writer.write_all(b"PING\r\nEXISTS a\r\n").unwrap();
+    assert_eq!(client.read(), Value::Simple("OK".to_string()));
+    assert_eq!(client.read(), bulk(b"1"));
+    assert_eq!(client.read(), Value::Simple("PONG".to_string()));
+    assert_eq!(client.read(), Value::Integer(1));
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn errors() {
+    let (addr, path) = start("errors");
+    let mut client = Client::connect(addr);
+    let Value::Error(err) = client.command(&[b"FLUSHALL"]) else { panic!("expected an error") };
+    assert!(err.starts_with("ERR unknown command"), "{}", err);
+    let Value::Error(err) = client.command(&[b"GET"]) else { panic!("expected an error") };
+    assert!(err.starts_with("ERR wrong number of arguments"), "{}", err);
+    // 出错之后连接仍然可用
+    assert_eq!(client.command(&[b"PING"]), Value::Simple("PONG".to_string()));
+
+    client.writer.write_all(b"*1\r\n#3\r\n").unwrap();
+    let Value::Error(err) = client.read() else { panic!("expected an error") };
+    assert!(err.starts_with("ERR Protocol error"), "{}", err);
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn follower_refuses_writes_until_promoted() {
+    // 主库同时提供 RESP 服务和复制服务
+    let leader_path = env::temp_dir().join(format!("actionkv-server-{}-leader", process::id()));
+    let _ = fs::remove_file(&leader_path);
+    let store = SharedActionKV::open(&leader_path).unwrap();
+    let (listener, replicas) = (TcpListener::bind("127.0.0.1:0").unwrap(), store.clone());
+    let replication = listener.local_addr().unwrap();
+    thread::spawn(move || libactionkv::serve_replication(listener, replicas));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut leader = Client::connect(listener.local_addr().unwrap());
+    thread::spawn(move || libactionkv::serve(listener, store));
+    leader.command(&[b"SET", b"a", b"1"]);
+
+    let path = env::temp_dir().join(format!("actionkv-server-{}-follower", process::id()));
+    let _ = fs::remove_file(&path);
+    let mut store = ActionKV::open(&path).unwrap();
+    store.load().unwrap();
+    let follower = Follower::start(store, replication).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || libactionkv::serve_follower(listener, follower));
+
+    let mut client = Client::connect(addr);
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while client.command(&[b"GET", b"a"]) != bulk(b"1") {
+        assert!(Instant::now() < deadline, "follower never caught up");
+        thread::sleep(Duration::from_millis(10));
+    }
+    for write in [&[&b"SET"[..], b"b", b"2"][..], &[b"DEL", b"a"]] {
+        let Value::Error(err) = client.command(write) else { panic!("expected an error") };
+        assert!(err.starts_with("READONLY"), "{}", err);
+    }
+    let Value::Error(err) = client.command(&[b"REPLICAOF", b"127.0.0.1", b"6379"]) else {
+        panic!("expected an error")
+    };
+    assert!(err.starts_with("ERR"), "{}", err);
+
+    // 提升之后可以写入，主库之后的写入不再复制过来
+    assert_eq!(client.command(&[b"REPLICAOF", b"NO", b"ONE"]), Value::Simple("OK".to_string()));
+    assert_eq!(client.command(&[b"SET", b"b", b"2"]), Value::Simple("OK".to_string()));
+    leader.command(&[b"SET", b"c", b"3"]);
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(client.command(&[b"GET", b"b"]), bulk(b"2"));
+    assert_eq!(client.command(&[b"GET", b"c"]), Value::Bulk(None));
+    assert_eq!(client.command(&[b"REPLICAOF", b"NO", b"ONE"]), Value::Simple("OK".to_string()));
+    fs::remove_file(&path).unwrap();
+    fs::remove_file(leader_path).unwrap();
+}
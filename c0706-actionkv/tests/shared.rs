@@ -0,0 +1,79 @@
+// SharedActionKV 在线程间共享：多个读者和写者同时访问同一个存储
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use libactionkv::{ActionKV, Durability, MemoryStorage, Options, SharedActionKV, Storage};
+
+const WRITERS: usize = 4;
+const WRITES: usize = 200;
+
+fn assert_shareable<T: Send + Sync + Clone>() {}
+
+fn open(storage: &Arc<dyn Storage>) -> ActionKV {
+    let options = Options { durability: Durability::Always, ..Options::default() };
+    let mut store = ActionKV::open_with_storage(storage.clone(), options).unwrap();
+    store.load().unwrap();
+    store
+}
+
+#[test]
+fn readers_and_writers_run_concurrently() {
+    assert_shareable::<SharedActionKV>();
+    let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+    let store = SharedActionKV::from(open(&storage));
+    let done = Arc::new(AtomicBool::new(false));
+
+    // 每个写者先写 w{t}-{i}，再把 last-{t} 改成 i
+    let writers: Vec<_> = (0..WRITERS)
+        .map(|t| {
+            let store = store.clone();
+            thread::spawn(move || {
+                for i in 0..WRITES {
+                    let value = i.to_string();
+                    store.insert(format!("w{}-{}", t, i).as_bytes(), value.as_bytes()).unwrap();
+                    store.insert(format!("last-{}", t).as_bytes(), value.as_bytes()).unwrap();
+                }
+            })
+        })
+        .collect();
+
+    // 读者看到 last-{t} 为 i 时，w{t}-{i} 一定已经写入；快照也一样
+    let readers: Vec<_> = (0..WRITERS)
+        .map(|t| {
+            let (store, done) = (store.clone(), done.clone());
+            thread::spawn(move || {
+                let mut reads = 0;
+                while !done.load(Ordering::SeqCst) {
+                    let last = format!("last-{}", t);
+                    if let Some(i) = store.get(last.as_bytes()).unwrap() {
+                        let key = format!("w{}-{}", t, String::from_utf8(i.clone()).unwrap());
+                        assert_eq!(store.get(key.as_bytes()).unwrap(), Some(i));
+                    }
+                    let snapshot = store.snapshot().unwrap();
+                    if let Some(i) = snapshot.get(last.as_bytes()).unwrap() {
+                        let key = format!("w{}-{}", t, String::from_utf8(i.clone()).unwrap());
+                        assert_eq!(snapshot.get(key.as_bytes()).unwrap(), Some(i));
+                    }
+                    reads += 1;
+                }
+                reads
+            })
+        })
+        .collect();
+
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    done.store(true, Ordering::SeqCst);
+    for reader in readers {
+        assert!(reader.join().unwrap() > 0);
+    }
+
+    assert_eq!(store.keys().len(), WRITERS * (WRITES + 1));
+    drop(store);
+    let store = open(&storage);
+    assert_eq!(store.keys().count(), WRITERS * (WRITES + 1));
+    assert_eq!(store.get(b"last-0").unwrap(), Some((WRITES - 1).to_string().into_bytes()));
+}
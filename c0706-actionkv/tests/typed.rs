@@ -0,0 +1,87 @@
+// TypedStore 的三种编码和解码错误
+
+use std::io;
+use std::sync::Arc;
+
+use libactionkv::{ActionKV, DecodeError, Encoding, MemoryStorage, Options, Storage, TypedStore};
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct User {
+    name: String,
+    age: u32,
+    tags: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Account {
+    balance: i64,
+}
+
+fn open(storage: &Arc<MemoryStorage>) -> ActionKV {
+    let storage: Arc<dyn Storage> = storage.clone();
+    let mut store = ActionKV::open_with_storage(storage, Options::default()).unwrap();
+    store.load().unwrap();
+    store
+}
+
+fn user(name: &str, age: u32) -> User {
+    User { name: name.to_string(), age, tags: vec!["admin".to_string()] }
+}
+
+#[test]
+fn round_trips_in_every_encoding() {
+    for encoding in [Encoding::Bincode, Encoding::Json, Encoding::Cbor] {
+        let storage = Arc::new(MemoryStorage::new());
+        let mut users: TypedStore<(String, u64), User> = TypedStore::new(open(&storage), encoding);
+        let alice = ("eu".to_string(), 1);
+        let bob = ("us".to_string(), 2);
+        users.insert(&alice, &user("alice", 30)).unwrap();
+        users.insert(&bob, &user("bob", 40)).unwrap();
+        users.delete(&bob).unwrap();
+
+        assert_eq!(users.get(&alice).unwrap(), Some(user("alice", 30)), "{}", encoding);
+        assert_eq!(users.get(&bob).unwrap(), None);
+        assert!(users.contains_key(&alice).unwrap());
+        let all: Vec<_> = users.iter().collect::<io::Result<_>>().unwrap();
+        assert_eq!(all, vec![(alice.clone(), user("alice", 30))]);
+        users.into_inner().close().unwrap();
+
+        // 重新打开后用同一种编码读取
+        let users: TypedStore<(String, u64), User> = TypedStore::new(open(&storage), encoding);
+        assert_eq!(users.keys().collect::<io::Result<Vec<_>>>().unwrap(), vec![alice]);
+    }
+}
+
+#[test]
+fn decode_errors_are_checked() {
+    let storage = Arc::new(MemoryStorage::new());
+    let mut users: TypedStore<String, User> = TypedStore::new(open(&storage), Encoding::Json);
+    users.insert(&"alice".to_string(), &user("alice", 30)).unwrap();
+    let mut store = users.into_inner();
+    store.insert(b"\"broken\"", b"{\"name\": \"bob\"").unwrap();
+
+    // 类型不对：value 是 User，当作 Account 读取
+    let accounts: TypedStore<String, Account> = TypedStore::new(store, Encoding::Json);
+    let err = accounts.get(&"alice".to_string()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    let decode = DecodeError::from_io_error(&err).unwrap();
+    assert_eq!(decode.encoding, Encoding::Json);
+    assert_eq!(decode.key, b"\"alice\"".to_vec());
+
+    // 数据不完整
+    let users: TypedStore<String, User> = TypedStore::new(accounts.into_inner(), Encoding::Json);
+    assert!(users.get(&"broken".to_string()).is_err());
+    assert!(users.iter().any(|kv| kv.is_err()));
+
+    // 编码不一致：JSON 写入的数据不能当作 bincode 或 CBOR 读取
+    for encoding in [Encoding::Bincode, Encoding::Cbor] {
+        let users: TypedStore<String, User> = TypedStore::new(open(&storage), encoding);
+        let errors: Vec<io::Error> = users.keys().filter_map(Result::err).collect();
+        assert!(!errors.is_empty(), "{}", encoding);
+        assert!(errors.iter().all(|err| DecodeError::from_io_error(err).is_some()));
+    }
+
+    assert_eq!("cbor".parse::<Encoding>().unwrap(), Encoding::Cbor);
+    assert!("yaml".parse::<Encoding>().is_err());
+}